@@ -1,12 +1,7 @@
-SET SEARCH_PATH = ipfsi;
+-- Matches the schema previously created by res/ddl/10_tables.sql and 99_changes.sql, so databases set up by
+-- hand can be adopted without dropping anything.
 
-DROP TABLE IF EXISTS node CASCADE;
-DROP TABLE IF EXISTS node_addr CASCADE;
-DROP TABLE IF EXISTS peer CASCADE;
-DROP TABLE IF EXISTS object CASCADE;
-DROP TABLE IF EXISTS node_object_pin CASCADE;
-
-CREATE TABLE node
+CREATE TABLE IF NOT EXISTS node
 (
     id          VARCHAR(64) NOT NULL,
     seen_first  timestamptz NOT NULL,
@@ -17,7 +12,10 @@ CREATE TABLE node
     CONSTRAINT node_pk PRIMARY KEY (id)
 );
 
-CREATE TABLE node_addr
+ALTER TABLE node
+    ADD COLUMN IF NOT EXISTS scan_last timestamptz;
+
+CREATE TABLE IF NOT EXISTS node_addr
 (
     id_node VARCHAR(64)  NOT NULL,
     addr    VARCHAR(128) NOT NULL,
@@ -27,7 +25,7 @@ CREATE TABLE node_addr
     CONSTRAINT node_addr_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
 );
 
-CREATE TABLE peer
+CREATE TABLE IF NOT EXISTS peer
 (
     id_left  VARCHAR(64) NOT NULL,
     id_right VARCHAR(64) NOT NULL,
@@ -38,7 +36,7 @@ CREATE TABLE peer
     CONSTRAINT peer_id_right_fk FOREIGN KEY (id_right) REFERENCES node (id)
 );
 
-CREATE TABLE object
+CREATE TABLE IF NOT EXISTS object
 (
     id   VARCHAR(64) NOT NULL,
     size BIGINT      NOT NULL,
@@ -46,7 +44,7 @@ CREATE TABLE object
     CONSTRAINT object_pk PRIMARY KEY (id)
 );
 
-CREATE TABLE node_object_pin
+CREATE TABLE IF NOT EXISTS node_object_pin
 (
     id_node   VARCHAR(64) NOT NULL,
     id_object VARCHAR(64) NOT NULL,
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS crawl_queue
+(
+    addr     VARCHAR(128) NOT NULL,
+    id_node  VARCHAR(64)  NOT NULL,
+    api_addr VARCHAR(128),
+    state    VARCHAR(16)  NOT NULL,
+    attempts INT          NOT NULL,
+    enqueued timestamptz  NOT NULL,
+    claimed  timestamptz,
+
+    CONSTRAINT crawl_queue_pk PRIMARY KEY (addr)
+);
+
+CREATE INDEX IF NOT EXISTS crawl_queue_state_enqueued_idx ON crawl_queue (state, enqueued);
@@ -0,0 +1,15 @@
+-- No foreign key on id_node: a seed or requeued node can fail before it was ever written to node.
+
+CREATE TABLE IF NOT EXISTS scan_error
+(
+    id       BIGSERIAL    NOT NULL,
+    id_node  VARCHAR(64)  NOT NULL,
+    addr     VARCHAR(128) NOT NULL,
+    kind     VARCHAR(16)  NOT NULL,
+    message  TEXT         NOT NULL,
+    occurred timestamptz  NOT NULL,
+
+    CONSTRAINT scan_error_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS scan_error_id_node_occurred_idx ON scan_error (id_node, occurred);
@@ -0,0 +1,13 @@
+-- Parsed parts of node_addr.addr. Relayed addresses carry two peer IDs, so addresses get more room too.
+
+ALTER TABLE node_addr
+    ALTER COLUMN addr TYPE VARCHAR(512),
+    ADD COLUMN IF NOT EXISTS transport VARCHAR(16),
+    ADD COLUMN IF NOT EXISTS host      VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS port      INT,
+    ADD COLUMN IF NOT EXISTS relay     BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE crawl_queue
+    ALTER COLUMN addr TYPE VARCHAR(512);
+
+CREATE INDEX IF NOT EXISTS node_addr_transport_idx ON node_addr (transport);
@@ -0,0 +1,2 @@
+ALTER TABLE node
+    ADD COLUMN IF NOT EXISTS api_candidate VARCHAR(32);
@@ -0,0 +1,15 @@
+-- Public HTTP gateways found on a node's hosts. A gateway that stops answering is kept with active=FALSE;
+-- latency_ms and subdomain are from the last check that succeeded.
+
+CREATE TABLE IF NOT EXISTS node_gateway
+(
+    id_node    VARCHAR(64)  NOT NULL,
+    url        VARCHAR(256) NOT NULL,
+    latency_ms INT          NOT NULL,
+    subdomain  BOOLEAN      NOT NULL,
+    check_last timestamptz  NOT NULL,
+    active     BOOLEAN      NOT NULL,
+
+    CONSTRAINT node_gateway_pk PRIMARY KEY (id_node, url),
+    CONSTRAINT node_gateway_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
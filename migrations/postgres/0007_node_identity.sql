@@ -0,0 +1,20 @@
+-- What a node reported about itself through its API. A new row is added whenever the agent, protocol version,
+-- key or protocols change; addresses and seen_last are kept current on the latest row.
+
+CREATE TABLE IF NOT EXISTS node_identity
+(
+    id               BIGSERIAL    NOT NULL,
+    id_node          VARCHAR(64)  NOT NULL,
+    agent_version    VARCHAR(256) NOT NULL,
+    protocol_version VARCHAR(64)  NOT NULL,
+    public_key       TEXT         NOT NULL,
+    protocols        VARCHAR[]    NOT NULL,
+    addresses        VARCHAR[]    NOT NULL,
+    seen_first       timestamptz  NOT NULL,
+    seen_last        timestamptz  NOT NULL,
+
+    CONSTRAINT node_identity_pk PRIMARY KEY (id),
+    CONSTRAINT node_identity_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
+
+CREATE INDEX IF NOT EXISTS node_identity_id_node_seen_last_idx ON node_identity (id_node, seen_last);
@@ -0,0 +1,17 @@
+-- An API that answered with a different peer ID than the one it was found through, e.g. because several nodes
+-- share an IP behind NAT or a reverse proxy. The address is only attributed to id_reported.
+
+CREATE TABLE IF NOT EXISTS node_id_mismatch
+(
+    id_advertised VARCHAR(64)  NOT NULL,
+    id_reported   VARCHAR(64)  NOT NULL,
+    api_addr      VARCHAR(512) NOT NULL,
+    -- The swarm address id_advertised was seen at, or the crawl_queue address that led to api_addr
+    addr          VARCHAR(512) NOT NULL,
+    seen_first    timestamptz  NOT NULL,
+    seen_last     timestamptz  NOT NULL,
+
+    CONSTRAINT node_id_mismatch_pk PRIMARY KEY (id_advertised, id_reported, api_addr),
+    CONSTRAINT node_id_mismatch_id_advertised_fk FOREIGN KEY (id_advertised) REFERENCES node (id),
+    CONSTRAINT node_id_mismatch_id_reported_fk FOREIGN KEY (id_reported) REFERENCES node (id)
+);
@@ -0,0 +1,15 @@
+-- Links between the blocks of pinned DAGs, found by walking down from the pin roots. Children aren't objects
+-- themselves, since nothing is known about them but their CID; walking back up from a CID through id_parent
+-- leads to the roots in node_object_pin that contain it.
+
+CREATE TABLE IF NOT EXISTS object_link
+(
+    id_parent VARCHAR(64)  NOT NULL,
+    id_child  VARCHAR(64)  NOT NULL,
+    -- Empty for unnamed links, e.g. the chunks of a file
+    name      VARCHAR(512) NOT NULL,
+
+    CONSTRAINT object_link_pk PRIMARY KEY (id_parent, id_child, name)
+);
+
+CREATE INDEX IF NOT EXISTS object_link_id_child_idx ON object_link (id_child);
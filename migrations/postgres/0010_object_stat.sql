@@ -0,0 +1,12 @@
+-- The rest of object/stat, and files/stat for UnixFS objects. size is object/stat's DataSize, which only covers
+-- the root block; cumulative_size covers the whole DAG. Rows from before this migration have NULLs until the
+-- object is seen again.
+
+ALTER TABLE object
+    ADD COLUMN IF NOT EXISTS cumulative_size BIGINT,
+    ADD COLUMN IF NOT EXISTS num_links       BIGINT,
+    ADD COLUMN IF NOT EXISTS block_size      BIGINT,
+    ADD COLUMN IF NOT EXISTS links_size      BIGINT,
+    ADD COLUMN IF NOT EXISTS file_type       VARCHAR(16),
+    ADD COLUMN IF NOT EXISTS file_size       BIGINT,
+    ADD COLUMN IF NOT EXISTS file_blocks     BIGINT;
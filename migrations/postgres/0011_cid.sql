@@ -0,0 +1,39 @@
+-- Room for CIDv1 strings and peer IDs in any encoding, e.g. base32 CIDs with 512-bit hashes, which run past
+-- 100 characters. Widening a VARCHAR doesn't rewrite the table.
+
+ALTER TABLE node
+    ALTER COLUMN id TYPE VARCHAR(128);
+ALTER TABLE node_addr
+    ALTER COLUMN id_node TYPE VARCHAR(128);
+ALTER TABLE peer
+    ALTER COLUMN id_left TYPE VARCHAR(128),
+    ALTER COLUMN id_right TYPE VARCHAR(128);
+ALTER TABLE crawl_queue
+    ALTER COLUMN id_node TYPE VARCHAR(128);
+ALTER TABLE scan_error
+    ALTER COLUMN id_node TYPE VARCHAR(128);
+ALTER TABLE node_gateway
+    ALTER COLUMN id_node TYPE VARCHAR(128);
+ALTER TABLE node_identity
+    ALTER COLUMN id_node TYPE VARCHAR(128);
+ALTER TABLE node_id_mismatch
+    ALTER COLUMN id_advertised TYPE VARCHAR(128),
+    ALTER COLUMN id_reported TYPE VARCHAR(128);
+
+ALTER TABLE object
+    ALTER COLUMN id TYPE VARCHAR(256);
+ALTER TABLE node_object_pin
+    ALTER COLUMN id_node TYPE VARCHAR(128),
+    ALTER COLUMN id_object TYPE VARCHAR(256);
+ALTER TABLE object_link
+    ALTER COLUMN id_parent TYPE VARCHAR(256),
+    ALTER COLUMN id_child TYPE VARCHAR(256);
+
+-- Object IDs are now stored as CIDv1 in base32, so the same content pinned as v0 and as v1 is one object. Rows
+-- from before this migration keep the ID pin/ls reported and have no version.
+ALTER TABLE object
+    ADD COLUMN IF NOT EXISTS cid_version   SMALLINT,
+    ADD COLUMN IF NOT EXISTS codec         VARCHAR(32),
+    ADD COLUMN IF NOT EXISTS hash_function VARCHAR(32);
+
+CREATE INDEX IF NOT EXISTS object_codec_idx ON object (codec);
@@ -0,0 +1,22 @@
+-- The pin type (recursive, direct or indirect) and when the pin was first and last listed. A pin that is no longer
+-- listed is deactivated rather than deleted, so unpins show up. Existing rows get the time of their node's last
+-- scan and no type.
+
+ALTER TABLE node_object_pin
+    ADD COLUMN IF NOT EXISTS pin_type   VARCHAR(16),
+    ADD COLUMN IF NOT EXISTS seen_first timestamptz,
+    ADD COLUMN IF NOT EXISTS seen_last  timestamptz,
+    ADD COLUMN IF NOT EXISTS active     BOOLEAN NOT NULL DEFAULT TRUE;
+
+UPDATE node_object_pin AS p
+SET seen_first=COALESCE(n.scan_last, n.seen_last),
+    seen_last=COALESCE(n.scan_last, n.seen_last)
+FROM node AS n
+WHERE n.id=p.id_node
+  AND p.seen_first IS NULL;
+
+ALTER TABLE node_object_pin
+    ALTER COLUMN seen_first SET NOT NULL,
+    ALTER COLUMN seen_last SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS node_object_pin_id_object_idx ON node_object_pin (id_object);
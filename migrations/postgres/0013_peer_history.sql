@@ -0,0 +1,41 @@
+-- When each edge was first and last seen, and what the last scan said about the connection. Every connection
+-- seen by a scan also goes into peer_observation, for looking at churn over time. Existing edges get the time of
+-- their node's last scan.
+
+ALTER TABLE peer
+    ADD COLUMN IF NOT EXISTS seen_first timestamptz,
+    ADD COLUMN IF NOT EXISTS seen_last  timestamptz,
+    ADD COLUMN IF NOT EXISTS addr       VARCHAR(512),
+    ADD COLUMN IF NOT EXISTS latency_ms INT,
+    -- inbound or outbound, from id_left's side
+    ADD COLUMN IF NOT EXISTS direction  VARCHAR(16),
+    ADD COLUMN IF NOT EXISTS muxer      VARCHAR(64);
+
+UPDATE peer AS p
+SET seen_first=COALESCE(n.scan_last, n.seen_last),
+    seen_last=COALESCE(n.scan_last, n.seen_last)
+FROM node AS n
+WHERE n.id=p.id_left
+  AND p.seen_first IS NULL;
+
+ALTER TABLE peer
+    ALTER COLUMN seen_first SET NOT NULL,
+    ALTER COLUMN seen_last SET NOT NULL;
+
+CREATE TABLE IF NOT EXISTS peer_observation
+(
+    id_left    VARCHAR(128) NOT NULL,
+    id_right   VARCHAR(128) NOT NULL,
+    observed   timestamptz  NOT NULL,
+    addr       VARCHAR(512) NOT NULL,
+    latency_ms INT,
+    direction  VARCHAR(16),
+    muxer      VARCHAR(64),
+    streams    INT          NOT NULL,
+
+    CONSTRAINT peer_observation_pk PRIMARY KEY (id_left, id_right, observed, addr),
+    CONSTRAINT peer_observation_id_left_fk FOREIGN KEY (id_left) REFERENCES node (id),
+    CONSTRAINT peer_observation_id_right_fk FOREIGN KEY (id_right) REFERENCES node (id)
+);
+
+CREATE INDEX IF NOT EXISTS peer_observation_observed_idx ON peer_observation (observed);
@@ -0,0 +1,63 @@
+-- One row per crawl or rescan process. Each scan records what it saw under its run: the node and the API it
+-- answered on in node_scan, its connections in peer_observation and its pins in pin_observation, so that two runs
+-- can be compared. Observations from before this migration have no run. The other rows a scan writes, and its
+-- errors, get the run that last wrote them.
+
+CREATE TABLE IF NOT EXISTS crawl_run
+(
+    id       BIGSERIAL   NOT NULL,
+    -- crawl or rescan
+    kind     VARCHAR(16) NOT NULL,
+    started  timestamptz NOT NULL,
+    finished timestamptz,
+
+    CONSTRAINT crawl_run_pk PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS node_scan
+(
+    id_run   BIGINT       NOT NULL,
+    id_node  VARCHAR(128) NOT NULL,
+    scanned  timestamptz  NOT NULL,
+    api_addr VARCHAR(512),
+
+    CONSTRAINT node_scan_pk PRIMARY KEY (id_run, id_node),
+    CONSTRAINT node_scan_id_run_fk FOREIGN KEY (id_run) REFERENCES crawl_run (id),
+    CONSTRAINT node_scan_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
+
+ALTER TABLE peer_observation ADD COLUMN IF NOT EXISTS id_run BIGINT;
+ALTER TABLE scan_error ADD COLUMN IF NOT EXISTS id_run BIGINT;
+ALTER TABLE node_identity ADD COLUMN IF NOT EXISTS id_run BIGINT;
+ALTER TABLE node_gateway ADD COLUMN IF NOT EXISTS id_run BIGINT;
+ALTER TABLE node_id_mismatch ADD COLUMN IF NOT EXISTS id_run BIGINT;
+
+-- ADD CONSTRAINT has no IF NOT EXISTS
+DO $$
+DECLARE
+    t TEXT;
+BEGIN
+    FOREACH t IN ARRAY ARRAY['peer_observation', 'scan_error', 'node_identity', 'node_gateway', 'node_id_mismatch'] LOOP
+        IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = t || '_id_run_fk') THEN
+            EXECUTE format('ALTER TABLE %I ADD CONSTRAINT %I FOREIGN KEY (id_run) REFERENCES crawl_run (id)',
+                           t, t || '_id_run_fk');
+        END IF;
+    END LOOP;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS peer_observation_id_run_idx ON peer_observation (id_run);
+CREATE INDEX IF NOT EXISTS scan_error_id_run_idx ON scan_error (id_run);
+
+CREATE TABLE IF NOT EXISTS pin_observation
+(
+    id_run    BIGINT       NOT NULL,
+    id_node   VARCHAR(128) NOT NULL,
+    id_object VARCHAR(256) NOT NULL,
+    pin_type  VARCHAR(16)  NOT NULL,
+
+    CONSTRAINT pin_observation_pk PRIMARY KEY (id_run, id_node, id_object),
+    CONSTRAINT pin_observation_id_run_fk FOREIGN KEY (id_run) REFERENCES crawl_run (id),
+    CONSTRAINT pin_observation_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id),
+    CONSTRAINT pin_observation_id_object_fk FOREIGN KEY (id_object) REFERENCES object (id)
+);
@@ -0,0 +1,6 @@
+-- API addresses can have DNS hosts of up to 253 characters, so they get the same room as swarm addresses.
+
+ALTER TABLE node
+    ALTER COLUMN public_addr TYPE VARCHAR(512);
+ALTER TABLE crawl_queue
+    ALTER COLUMN api_addr TYPE VARCHAR(512);
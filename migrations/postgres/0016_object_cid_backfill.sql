@@ -0,0 +1,6 @@
+-- Objects stored before 0011 keep the ID pin/ls reported, often a CIDv0, so the same content can exist under two
+-- IDs. SQL can't decode CIDs, so the rows are rewritten under their canonical ID by the migrate step that runs
+-- after the migrations (normalize_object_ids), which also fills in their version, codec and hash function. This
+-- index lets it find the rows left to do.
+
+CREATE INDEX IF NOT EXISTS object_cid_version_null_idx ON object (id) WHERE cid_version IS NULL;
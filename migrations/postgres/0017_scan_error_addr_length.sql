@@ -0,0 +1,4 @@
+-- scan_error.addr holds the same swarm and API addresses as node_addr and crawl_queue, so it gets the same room.
+
+ALTER TABLE scan_error
+    ALTER COLUMN addr TYPE VARCHAR(512);
@@ -0,0 +1,51 @@
+-- Timestamps are stored as RFC 3339 text, which sorts chronologically.
+
+CREATE TABLE IF NOT EXISTS node
+(
+    id          TEXT NOT NULL,
+    seen_first  TEXT NOT NULL,
+    seen_last   TEXT NOT NULL,
+    scan_last   TEXT,
+    public_addr TEXT,
+
+    CONSTRAINT node_pk PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS node_addr
+(
+    id_node TEXT    NOT NULL,
+    addr    TEXT    NOT NULL,
+    active  BOOLEAN NOT NULL,
+
+    CONSTRAINT node_addr_pk PRIMARY KEY (id_node, addr),
+    CONSTRAINT node_addr_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
+
+CREATE TABLE IF NOT EXISTS peer
+(
+    id_left  TEXT    NOT NULL,
+    id_right TEXT    NOT NULL,
+    active   BOOLEAN NOT NULL,
+
+    CONSTRAINT peer_pk PRIMARY KEY (id_left, id_right),
+    CONSTRAINT peer_id_left_fk FOREIGN KEY (id_left) REFERENCES node (id),
+    CONSTRAINT peer_id_right_fk FOREIGN KEY (id_right) REFERENCES node (id)
+);
+
+CREATE TABLE IF NOT EXISTS object
+(
+    id   TEXT    NOT NULL,
+    size INTEGER NOT NULL,
+
+    CONSTRAINT object_pk PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS node_object_pin
+(
+    id_node   TEXT NOT NULL,
+    id_object TEXT NOT NULL,
+
+    CONSTRAINT node_object_pin_pk PRIMARY KEY (id_node, id_object),
+    CONSTRAINT node_object_pin_node_id_fk FOREIGN KEY (id_node) REFERENCES node (id),
+    CONSTRAINT node_object_pin_object_id_fk FOREIGN KEY (id_object) REFERENCES object (id)
+);
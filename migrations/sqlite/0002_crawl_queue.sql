@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS crawl_queue
+(
+    addr     TEXT    NOT NULL,
+    id_node  TEXT    NOT NULL,
+    api_addr TEXT,
+    state    TEXT    NOT NULL,
+    attempts INTEGER NOT NULL,
+    enqueued TEXT    NOT NULL,
+    claimed  TEXT,
+
+    CONSTRAINT crawl_queue_pk PRIMARY KEY (addr)
+);
+
+CREATE INDEX IF NOT EXISTS crawl_queue_state_enqueued_idx ON crawl_queue (state, enqueued);
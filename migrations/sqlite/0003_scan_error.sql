@@ -0,0 +1,15 @@
+-- No foreign key on id_node: a seed or requeued node can fail before it was ever written to node.
+
+CREATE TABLE IF NOT EXISTS scan_error
+(
+    id       INTEGER NOT NULL,
+    id_node  TEXT    NOT NULL,
+    addr     TEXT    NOT NULL,
+    kind     TEXT    NOT NULL,
+    message  TEXT    NOT NULL,
+    occurred TEXT    NOT NULL,
+
+    CONSTRAINT scan_error_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS scan_error_id_node_occurred_idx ON scan_error (id_node, occurred);
@@ -0,0 +1,8 @@
+-- Parsed parts of node_addr.addr.
+
+ALTER TABLE node_addr ADD COLUMN transport TEXT;
+ALTER TABLE node_addr ADD COLUMN host TEXT;
+ALTER TABLE node_addr ADD COLUMN port INTEGER;
+ALTER TABLE node_addr ADD COLUMN relay BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS node_addr_transport_idx ON node_addr (transport);
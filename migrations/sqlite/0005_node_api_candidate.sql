@@ -0,0 +1 @@
+ALTER TABLE node ADD COLUMN api_candidate TEXT;
@@ -0,0 +1,21 @@
+-- What a node reported about itself through its API. A new row is added whenever the agent, protocol version,
+-- key or protocols change; addresses and seen_last are kept current on the latest row. protocols and addresses
+-- are JSON arrays.
+
+CREATE TABLE IF NOT EXISTS node_identity
+(
+    id               INTEGER NOT NULL,
+    id_node          TEXT    NOT NULL,
+    agent_version    TEXT    NOT NULL,
+    protocol_version TEXT    NOT NULL,
+    public_key       TEXT    NOT NULL,
+    protocols        TEXT    NOT NULL,
+    addresses        TEXT    NOT NULL,
+    seen_first       TEXT    NOT NULL,
+    seen_last        TEXT    NOT NULL,
+
+    CONSTRAINT node_identity_pk PRIMARY KEY (id),
+    CONSTRAINT node_identity_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
+
+CREATE INDEX IF NOT EXISTS node_identity_id_node_seen_last_idx ON node_identity (id_node, seen_last);
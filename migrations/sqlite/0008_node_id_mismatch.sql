@@ -0,0 +1,16 @@
+-- An API that answered with a different peer ID than the one it was found through, e.g. because several nodes
+-- share an IP behind NAT or a reverse proxy. The address is only attributed to id_reported.
+
+CREATE TABLE IF NOT EXISTS node_id_mismatch
+(
+    id_advertised TEXT NOT NULL,
+    id_reported   TEXT NOT NULL,
+    api_addr      TEXT NOT NULL,
+    addr          TEXT NOT NULL,
+    seen_first    TEXT NOT NULL,
+    seen_last     TEXT NOT NULL,
+
+    CONSTRAINT node_id_mismatch_pk PRIMARY KEY (id_advertised, id_reported, api_addr),
+    CONSTRAINT node_id_mismatch_id_advertised_fk FOREIGN KEY (id_advertised) REFERENCES node (id),
+    CONSTRAINT node_id_mismatch_id_reported_fk FOREIGN KEY (id_reported) REFERENCES node (id)
+);
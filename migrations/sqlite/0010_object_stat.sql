@@ -0,0 +1,11 @@
+-- The rest of object/stat, and files/stat for UnixFS objects. size is object/stat's DataSize, which only covers
+-- the root block; cumulative_size covers the whole DAG. Rows from before this migration have NULLs until the
+-- object is seen again.
+
+ALTER TABLE object ADD COLUMN cumulative_size INTEGER;
+ALTER TABLE object ADD COLUMN num_links INTEGER;
+ALTER TABLE object ADD COLUMN block_size INTEGER;
+ALTER TABLE object ADD COLUMN links_size INTEGER;
+ALTER TABLE object ADD COLUMN file_type TEXT;
+ALTER TABLE object ADD COLUMN file_size INTEGER;
+ALTER TABLE object ADD COLUMN file_blocks INTEGER;
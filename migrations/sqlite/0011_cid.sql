@@ -0,0 +1,9 @@
+-- Object IDs are now stored as CIDv1 in base32, so the same content pinned as v0 and as v1 is one object. Rows
+-- from before this migration keep the ID pin/ls reported and have no version. SQLite doesn't enforce VARCHAR
+-- lengths, so unlike Postgres nothing needs widening.
+
+ALTER TABLE object ADD COLUMN cid_version INTEGER;
+ALTER TABLE object ADD COLUMN codec TEXT;
+ALTER TABLE object ADD COLUMN hash_function TEXT;
+
+CREATE INDEX IF NOT EXISTS object_codec_idx ON object (codec);
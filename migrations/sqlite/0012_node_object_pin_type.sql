@@ -0,0 +1,15 @@
+-- The pin type (recursive, direct or indirect) and when the pin was first and last listed. A pin that is no longer
+-- listed is deactivated rather than deleted, so unpins show up. Existing rows get the time of their node's last
+-- scan and no type.
+
+ALTER TABLE node_object_pin ADD COLUMN pin_type TEXT;
+ALTER TABLE node_object_pin ADD COLUMN seen_first TEXT;
+ALTER TABLE node_object_pin ADD COLUMN seen_last TEXT;
+ALTER TABLE node_object_pin ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
+
+UPDATE node_object_pin
+SET seen_first=(SELECT COALESCE(scan_last, seen_last) FROM node WHERE id=node_object_pin.id_node),
+    seen_last=(SELECT COALESCE(scan_last, seen_last) FROM node WHERE id=node_object_pin.id_node)
+WHERE seen_first IS NULL;
+
+CREATE INDEX IF NOT EXISTS node_object_pin_id_object_idx ON node_object_pin (id_object);
@@ -0,0 +1,33 @@
+-- When each edge was first and last seen, and what the last scan said about the connection. Every connection
+-- seen by a scan also goes into peer_observation, for looking at churn over time. Existing edges get the time of
+-- their node's last scan.
+
+ALTER TABLE peer ADD COLUMN seen_first TEXT;
+ALTER TABLE peer ADD COLUMN seen_last TEXT;
+ALTER TABLE peer ADD COLUMN addr TEXT;
+ALTER TABLE peer ADD COLUMN latency_ms INTEGER;
+ALTER TABLE peer ADD COLUMN direction TEXT;
+ALTER TABLE peer ADD COLUMN muxer TEXT;
+
+UPDATE peer
+SET seen_first=(SELECT COALESCE(scan_last, seen_last) FROM node WHERE id=peer.id_left),
+    seen_last=(SELECT COALESCE(scan_last, seen_last) FROM node WHERE id=peer.id_left)
+WHERE seen_first IS NULL;
+
+CREATE TABLE IF NOT EXISTS peer_observation
+(
+    id_left    TEXT    NOT NULL,
+    id_right   TEXT    NOT NULL,
+    observed   TEXT    NOT NULL,
+    addr       TEXT    NOT NULL,
+    latency_ms INTEGER,
+    direction  TEXT,
+    muxer      TEXT,
+    streams    INTEGER NOT NULL,
+
+    CONSTRAINT peer_observation_pk PRIMARY KEY (id_left, id_right, observed, addr),
+    CONSTRAINT peer_observation_id_left_fk FOREIGN KEY (id_left) REFERENCES node (id),
+    CONSTRAINT peer_observation_id_right_fk FOREIGN KEY (id_right) REFERENCES node (id)
+);
+
+CREATE INDEX IF NOT EXISTS peer_observation_observed_idx ON peer_observation (observed);
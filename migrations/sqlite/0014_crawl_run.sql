@@ -0,0 +1,49 @@
+-- One row per crawl or rescan process. Each scan records what it saw under its run: the node and the API it
+-- answered on in node_scan, its connections in peer_observation and its pins in pin_observation, so that two runs
+-- can be compared. Observations from before this migration have no run. The other rows a scan writes, and its
+-- errors, get the run that last wrote them.
+
+CREATE TABLE IF NOT EXISTS crawl_run
+(
+    id       INTEGER NOT NULL,
+    -- crawl or rescan
+    kind     TEXT    NOT NULL,
+    started  TEXT    NOT NULL,
+    finished TEXT,
+
+    CONSTRAINT crawl_run_pk PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS node_scan
+(
+    id_run   INTEGER NOT NULL,
+    id_node  TEXT    NOT NULL,
+    scanned  TEXT    NOT NULL,
+    api_addr TEXT,
+
+    CONSTRAINT node_scan_pk PRIMARY KEY (id_run, id_node),
+    CONSTRAINT node_scan_id_run_fk FOREIGN KEY (id_run) REFERENCES crawl_run (id),
+    CONSTRAINT node_scan_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id)
+);
+
+ALTER TABLE peer_observation ADD COLUMN id_run INTEGER REFERENCES crawl_run (id);
+ALTER TABLE scan_error ADD COLUMN id_run INTEGER REFERENCES crawl_run (id);
+ALTER TABLE node_identity ADD COLUMN id_run INTEGER REFERENCES crawl_run (id);
+ALTER TABLE node_gateway ADD COLUMN id_run INTEGER REFERENCES crawl_run (id);
+ALTER TABLE node_id_mismatch ADD COLUMN id_run INTEGER REFERENCES crawl_run (id);
+
+CREATE INDEX IF NOT EXISTS peer_observation_id_run_idx ON peer_observation (id_run);
+CREATE INDEX IF NOT EXISTS scan_error_id_run_idx ON scan_error (id_run);
+
+CREATE TABLE IF NOT EXISTS pin_observation
+(
+    id_run    INTEGER NOT NULL,
+    id_node   TEXT    NOT NULL,
+    id_object TEXT    NOT NULL,
+    pin_type  TEXT    NOT NULL,
+
+    CONSTRAINT pin_observation_pk PRIMARY KEY (id_run, id_node, id_object),
+    CONSTRAINT pin_observation_id_run_fk FOREIGN KEY (id_run) REFERENCES crawl_run (id),
+    CONSTRAINT pin_observation_id_node_fk FOREIGN KEY (id_node) REFERENCES node (id),
+    CONSTRAINT pin_observation_id_object_fk FOREIGN KEY (id_object) REFERENCES object (id)
+);
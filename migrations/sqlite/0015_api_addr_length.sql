@@ -0,0 +1,2 @@
+-- API addresses can have DNS hosts of up to 253 characters. SQLite doesn't enforce VARCHAR lengths, so unlike
+-- Postgres nothing needs widening; this keeps the migration versions of the two backends in step.
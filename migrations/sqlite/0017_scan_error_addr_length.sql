@@ -0,0 +1,2 @@
+-- scan_error.addr holds the same swarm and API addresses as node_addr and crawl_queue. SQLite doesn't enforce
+-- VARCHAR lengths, so unlike Postgres nothing needs widening; this keeps the migration versions in step.
@@ -1,2 +0,0 @@
-ALTER TABLE node
-    ADD COLUMN scan_last timestamptz;
@@ -0,0 +1,272 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use crate::cid;
+use crate::config::ApiConfig;
+use crate::db::schema::Stats;
+use crate::db::store::Store;
+
+#[derive(Deserialize)]
+struct PageQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Clone, Copy)]
+struct Page {
+    limit: i64,
+    offset: i64,
+}
+
+// Every list endpoint wraps its rows in this, so that clients can tell where the next page starts
+#[derive(Serialize)]
+struct Paged<T> {
+    items: Vec<T>,
+    limit: i64,
+    offset: i64,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+// Stats count and sum over whole tables, so a read is kept for api.stats_ttl and shared by /stats and the GraphQL
+// stats field. The lock is held while counting, so requests that come in meanwhile wait for that read instead of
+// each starting their own.
+pub struct StatsCache {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, Stats)>>,
+}
+
+impl StatsCache {
+    pub fn new(ttl: Duration) -> Self {
+        StatsCache { ttl, last: Mutex::new(None) }
+    }
+
+    pub async fn get(&self, db: &dyn Store) -> anyhow::Result<Stats> {
+        let mut last = self.last.lock().await;
+
+        if let Some((read_at, stats)) = last.as_ref() {
+            if read_at.elapsed() < self.ttl {
+                return Ok(stats.clone());
+            }
+        }
+
+        let stats = db.get_stats().await?;
+        *last = Some((Instant::now(), stats.clone()));
+
+        Ok(stats)
+    }
+}
+
+// Serves the read-only JSON API until SIGINT.
+//
+//   GET /stats
+//   GET /nodes/public
+//   GET /nodes/<peer ID>
+//   GET /nodes/<peer ID>/peers
+//   GET /nodes/<peer ID>/pins
+//   GET /objects/<CID>/providers
+//
+// Lists take ?limit=&offset= and are ordered by ID. Built with the graphql feature, POST /graphql is served too.
+pub async fn serve(config: &ApiConfig, db: Arc<dyn Store>) -> anyhow::Result<()> {
+    let addr: SocketAddr = config.listen.parse()
+        .map_err(|e| anyhow!("invalid api.listen address '{}': {}", config.listen, e))?;
+
+    let (addr, server) = warp::serve(routes(config, db))
+        .try_bind_with_graceful_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .map_err(|e| anyhow!("failed to bind {}: {}", addr, e))?;
+
+    println!("Serving the API on http://{}", addr);
+    server.await;
+
+    Ok(())
+}
+
+// routes is only rebound to add /graphql when built with the graphql feature
+#[allow(clippy::let_and_return)]
+fn routes(config: &ApiConfig, db: Arc<dyn Store>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let stats_cache = Arc::new(StatsCache::new(config.stats_ttl()));
+
+    #[cfg(feature = "graphql")]
+    let graphql = crate::graphql::routes(config, db.clone(), stats_cache.clone());
+
+    let with_db = warp::any().map(move || db.clone());
+
+    let (page_size, max_page_size) = (config.page_size, config.max_page_size);
+    let page = warp::query::<PageQuery>().map(move |q: PageQuery| Page {
+        limit: q.limit.unwrap_or(page_size).min(max_page_size).max(1),
+        offset: q.offset.unwrap_or(0).max(0),
+    });
+
+    let stats = warp::path!("stats")
+        .and(with_db.clone())
+        .and(warp::any().map(move || stats_cache.clone()))
+        .and_then(get_stats);
+
+    let public_nodes = warp::path!("nodes" / "public")
+        .and(with_db.clone())
+        .and(page.clone())
+        .and_then(get_public_nodes);
+
+    let node = warp::path!("nodes" / String)
+        .and(with_db.clone())
+        .and_then(get_node);
+
+    let node_peers = warp::path!("nodes" / String / "peers")
+        .and(with_db.clone())
+        .and(page.clone())
+        .and_then(get_node_peers);
+
+    let node_pins = warp::path!("nodes" / String / "pins")
+        .and(with_db.clone())
+        .and(page.clone())
+        .and_then(get_node_pins);
+
+    let providers = warp::path!("objects" / String / "providers")
+        .and(with_db)
+        .and(page)
+        .and_then(get_providers);
+
+    // /nodes/public has to be tried before /nodes/<peer ID>
+    let routes = warp::get().and(
+        stats
+            .or(public_nodes)
+            .or(node)
+            .or(node_peers)
+            .or(node_pins)
+            .or(providers)
+    );
+
+    #[cfg(feature = "graphql")]
+    let routes = routes.or(graphql);
+
+    routes
+}
+
+async fn get_stats(db: Arc<dyn Store>, cache: Arc<StatsCache>) -> Result<Response, Infallible> {
+    Ok(json(cache.get(db.as_ref()).await))
+}
+
+async fn get_public_nodes(db: Arc<dyn Store>, page: Page) -> Result<Response, Infallible> {
+    Ok(paged(db.get_public_nodes(page.limit, page.offset).await, page))
+}
+
+async fn get_node(id: String, db: Arc<dyn Store>) -> Result<Response, Infallible> {
+    Ok(match db.get_node(&id).await {
+        Ok(Some(node)) => reply::json(&node).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, format!("no node with ID {}", id)),
+        Err(e) => internal_error(e),
+    })
+}
+
+async fn get_node_peers(id: String, db: Arc<dyn Store>, page: Page) -> Result<Response, Infallible> {
+    Ok(paged(db.get_node_peers(&id, page.limit, page.offset).await, page))
+}
+
+async fn get_node_pins(id: String, db: Arc<dyn Store>, page: Page) -> Result<Response, Infallible> {
+    Ok(paged(db.get_node_pins(&id, page.limit, page.offset).await, page))
+}
+
+async fn get_providers(cid: String, db: Arc<dyn Store>, page: Page) -> Result<Response, Infallible> {
+    Ok(paged(db.get_providers(&cid::normalize(&cid), page.limit, page.offset).await, page))
+}
+
+fn paged<T: Serialize>(r: anyhow::Result<Vec<T>>, page: Page) -> Response {
+    json(r.map(|items| Paged { items, limit: page.limit, offset: page.offset }))
+}
+
+fn json<T: Serialize>(r: anyhow::Result<T>) -> Response {
+    match r {
+        Ok(v) => reply::json(&v).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+// The cause is logged rather than returned, since it can include SQL
+fn internal_error(e: anyhow::Error) -> Response {
+    println!("! api: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error".to_owned())
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    reply::with_status(reply::json(&ErrorBody { error: message }), status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::db::memory::MemoryStore;
+    use crate::db::schema::Node;
+
+    fn node(id: &str) -> Node {
+        Node {
+            id: id.to_owned(),
+            seen_first: Utc::now(),
+            seen_last: Utc::now(),
+            scan_last: None,
+            public_addr: Some(format!("http://{}.example:5001", id)),
+            api_candidate: Some("5001/http".to_owned()),
+        }
+    }
+
+    async fn get(config: &ApiConfig, db: Arc<dyn Store>, path: &str) -> (StatusCode, String) {
+        let r = warp::test::request().path(path).reply(&routes(config, db)).await;
+        (r.status(), String::from_utf8(r.body().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn stats_are_kept_for_the_ttl() {
+        let db: Arc<dyn Store> = Arc::new(MemoryStore::default());
+        let counted = r#"{"nodes":1,"nodes_public":1,"nodes_scanned":0,"peers_active":0,"objects":0,"pins":0,"objects_bytes":0,"pins_bytes":0,"queue_pending":0}"#;
+
+        let config = ApiConfig::default();
+        let routes = routes(&config, db.clone());
+        let r = warp::test::request().path("/stats").reply(&routes).await;
+        assert_eq!(r.status(), StatusCode::OK);
+        assert!(r.body().starts_with(br#"{"nodes":0,"#));
+
+        db.add_node(&node("QmA")).await.unwrap();
+        let r = warp::test::request().path("/stats").reply(&routes).await;
+        assert!(r.body().starts_with(br#"{"nodes":0,"#));
+
+        let config = ApiConfig { stats_ttl: 0, ..ApiConfig::default() };
+        assert_eq!(get(&config, db, "/stats").await, (StatusCode::OK, counted.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn unknown_nodes_are_not_found() {
+        let db: Arc<dyn Store> = Arc::new(MemoryStore::default());
+
+        let (status, body) = get(&ApiConfig::default(), db, "/nodes/QmA").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"no node with ID QmA"}"#);
+    }
+
+    #[tokio::test]
+    async fn limits_are_capped_at_max_page_size() {
+        let db: Arc<dyn Store> = Arc::new(MemoryStore::default());
+        db.add_node(&node("QmA")).await.unwrap();
+        db.add_node(&node("QmB")).await.unwrap();
+
+        let config = ApiConfig { max_page_size: 1, ..ApiConfig::default() };
+        let (status, body) = get(&config, db, "/nodes/public?limit=10&offset=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"{"items":[{"id":"QmB","#), "{}", body);
+        assert!(body.ends_with(r#"}],"limit":1,"offset":1}"#), "{}", body);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+use std::str::FromStr;
+
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE36: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const BASE16: &[u8] = b"0123456789abcdef";
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+const DAG_PB: u64 = 0x70;
+
+// A content identifier. Whatever form it was parsed from, it displays as CIDv1 in base32, the form kubo's
+// `ipfs cid base32` gives, so the same content always has the same string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cid {
+    // The version it was parsed from
+    pub version: u64,
+    pub codec: u64,
+    pub hash_function: u64,
+    multihash: Vec<u8>,
+}
+
+impl Cid {
+    fn new(version: u64, codec: u64, multihash: &[u8]) -> Result<Cid, String> {
+        let mut r = multihash;
+        let hash_function = read_varint(&mut r)?;
+        let length = read_varint(&mut r)?;
+        if r.len() as u64 != length {
+            return Err(format!("digest is {} bytes instead of {}", r.len(), length));
+        }
+
+        Ok(Cid {
+            version,
+            codec,
+            hash_function,
+            multihash: multihash.to_vec(),
+        })
+    }
+
+    pub fn codec_name(&self) -> String {
+        let name = match self.codec {
+            0x51 => "cbor",
+            0x55 => "raw",
+            0x70 => "dag-pb",
+            0x71 => "dag-cbor",
+            0x72 => "libp2p-key",
+            0x78 => "git-raw",
+            0x0129 => "dag-json",
+            0x0200 => "json",
+            0x0202 => "car",
+            _ => return format!("0x{:x}", self.codec),
+        };
+
+        name.to_owned()
+    }
+
+    pub fn hash_function_name(&self) -> String {
+        let name = match self.hash_function {
+            0x00 => "identity",
+            0x11 => "sha1",
+            0x12 => "sha2-256",
+            0x13 => "sha2-512",
+            0x16 => "sha3-256",
+            0x14 => "sha3-512",
+            0x1b => "keccak-256",
+            0x1e => "blake3",
+            0xb220 => "blake2b-256",
+            0xb240 => "blake2b-512",
+            _ => return format!("0x{:x}", self.hash_function),
+        };
+
+        name.to_owned()
+    }
+}
+
+// The canonical form of a CID string, or the string itself if it isn't a CID this can parse.
+pub fn normalize(s: &str) -> String {
+    s.parse::<Cid>().map(|v| v.to_string()).unwrap_or_else(|_| s.to_owned())
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = Vec::with_capacity(self.multihash.len() + 4);
+        write_varint(&mut bytes, 1);
+        write_varint(&mut bytes, self.codec);
+        bytes.extend_from_slice(&self.multihash);
+
+        write!(f, "b{}", encode_bits(&bytes, BASE32, 5))
+    }
+}
+
+impl FromStr for Cid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cid, String> {
+        // A CIDv0 is a bare base58btc sha2-256 multihash, which always comes out as 46 characters starting with Qm.
+        if s.len() == 46 && s.starts_with("Qm") {
+            return Cid::new(0, DAG_PB, &decode_base_n(s, BASE58)?);
+        }
+
+        let mut chars = s.chars();
+        let prefix = chars.next().ok_or_else(|| "empty CID".to_owned())?;
+        let rest = chars.as_str();
+
+        let bytes = match prefix {
+            'b' => decode_bits(rest, BASE32, 5)?,
+            'B' => decode_bits(&rest.to_ascii_lowercase(), BASE32, 5)?,
+            'z' => decode_base_n(rest, BASE58)?,
+            'k' => decode_base_n(rest, BASE36)?,
+            'K' => decode_base_n(&rest.to_ascii_lowercase(), BASE36)?,
+            'f' => decode_bits(rest, BASE16, 4)?,
+            'F' => decode_bits(&rest.to_ascii_lowercase(), BASE16, 4)?,
+            'm' => decode_bits(rest, BASE64, 6)?,
+            'u' => decode_bits(rest, BASE64_URL, 6)?,
+            _ => return Err(format!("unsupported multibase prefix '{}'", prefix)),
+        };
+
+        let mut r = &bytes[..];
+        let version = read_varint(&mut r)?;
+        if version != 1 {
+            return Err(format!("unsupported CID version {}", version));
+        }
+        let codec = read_varint(&mut r)?;
+
+        Cid::new(1, codec, r)
+    }
+}
+
+// Unsigned LEB128, as used throughout multiformats
+fn read_varint(r: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+
+    for i in 0..9 {
+        let (&byte, rest) = r.split_first().ok_or_else(|| "truncated varint".to_owned())?;
+        *r = rest;
+
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("varint is too long".to_owned())
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// For the bases whose digits map to a whole number of bits: base16, base32 and base64. Padding is ignored.
+fn decode_bits(s: &str, alphabet: &[u8], bits_per_char: u32) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len() * bits_per_char as usize / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in s.bytes().filter(|&c| c != b'=') {
+        let digit = alphabet.iter()
+            .position(|&v| v == c)
+            .ok_or_else(|| format!("invalid character '{}'", c as char))?;
+
+        buffer = (buffer << bits_per_char) | digit as u32;
+        bits += bits_per_char;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(out)
+}
+
+fn encode_bits(bytes: &[u8], alphabet: &[u8], bits_per_char: u32) -> String {
+    let mask = (1 << bits_per_char) - 1;
+    let mut out = String::with_capacity(bytes.len() * 8 / bits_per_char as usize + 1);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= bits_per_char {
+            bits -= bits_per_char;
+            out.push(alphabet[((buffer >> bits) & mask) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(alphabet[((buffer << (bits_per_char - bits)) & mask) as usize] as char);
+    }
+
+    out
+}
+
+// For base58 and base36, which need big number division. Leading zero digits stand for leading zero bytes.
+fn decode_base_n(s: &str, alphabet: &[u8]) -> Result<Vec<u8>, String> {
+    let base = alphabet.len() as u32;
+    // Little-endian while decoding
+    let mut out: Vec<u8> = Vec::with_capacity(s.len());
+
+    for c in s.bytes() {
+        let mut carry = alphabet.iter()
+            .position(|&v| v == c)
+            .ok_or_else(|| format!("invalid character '{}'", c as char))? as u32;
+
+        for byte in out.iter_mut() {
+            carry += *byte as u32 * base;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            out.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = s.bytes().take_while(|&c| c == alphabet[0]).count();
+    out.resize(out.len() + zeros, 0);
+    out.reverse();
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_DIR_V0: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+    const EMPTY_DIR_V1: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+
+    #[test]
+    fn v0_becomes_v1_base32() {
+        let v: Cid = EMPTY_DIR_V0.parse().unwrap();
+        assert_eq!(v.version, 0);
+        assert_eq!(v.codec_name(), "dag-pb");
+        assert_eq!(v.hash_function_name(), "sha2-256");
+        assert_eq!(v.to_string(), EMPTY_DIR_V1);
+
+        let v: Cid = EMPTY_DIR_V1.parse().unwrap();
+        assert_eq!(v.version, 1);
+        assert_eq!(v.to_string(), EMPTY_DIR_V1);
+
+        assert_eq!(normalize("QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"),
+                   "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi");
+    }
+
+    #[test]
+    fn every_multibase_prefix_gives_the_same_cid() {
+        for s in &[
+            "BAFYBEICZSSCDSBS7FFQZ55ASQDF3SMV6KLCW3GOFSZVWLYARCI47BGF354",
+            "zdj7WbTaiJT1fgatdet9Ei9iDB5hdCxkbVyhyh8YTUnXMiwYi",
+            "k2jmtxtlhjl3fhmgndf92e48by79ryjuvqp3y2qgehpao6v3lurvnmcv",
+            "K2JMTXTLHJL3FHMGNDF92E48BY79RYJUVQP3Y2QGEHPAO6V3LURVNMCV",
+            "f0170122059948439065f29619ef41280cbb932be52c56d99c5966b65e0111239f098bbef",
+            "F0170122059948439065F29619EF41280CBB932BE52C56D99C5966B65E0111239F098BBEF",
+            "mAXASIFmUhDkGXylhnvQSgMu5Mr5SxW2ZxZZrZeAREjnwmLvv",
+            "uAXASIFmUhDkGXylhnvQSgMu5Mr5SxW2ZxZZrZeAREjnwmLvv",
+        ] {
+            assert_eq!(normalize(s), EMPTY_DIR_V1, "{}", s);
+        }
+    }
+
+    #[test]
+    fn keeps_the_codec() {
+        let v: Cid = "bafkreiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354".parse().unwrap();
+        assert_eq!(v.codec_name(), "raw");
+        assert_eq!(v.to_string(), "bafkreiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354");
+    }
+
+    #[test]
+    fn rejects_malformed_cids() {
+        for s in &[
+            "",
+            // Unknown multibase prefix
+            "xafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354",
+            // 0 isn't a base58 digit
+            "Qm0NLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+            // Not a base32 digit
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf35!",
+            // Digest cut short
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwly",
+            // Version 2
+            "f0270122059948439065f29619ef41280cbb932be52c56d99c5966b65e0111239f098bbef",
+            // Truncated varint
+            "f01",
+        ] {
+            assert!(s.parse::<Cid>().is_err(), "{} parsed", s);
+        }
+
+        assert_eq!(normalize("not a cid"), "not a cid");
+    }
+}
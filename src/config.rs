@@ -0,0 +1,541 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::export::graph::{EdgeFilter, GraphFormat};
+use crate::export::tables::{Table, TableFormat};
+
+const DEFAULT_CONFIG_PATH: &str = "ipfsi.toml";
+
+#[derive(StructOpt)]
+#[structopt(name = "ipfs-explorer", about = "Crawls the IPFS swarm and indexes nodes, peers and pins")]
+pub struct Cli {
+    /// Path to the TOML config file [default: ipfsi.toml, if present]
+    #[structopt(short, long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Crawl the swarm starting from the seed node
+    Crawl {
+        /// Multiaddr of the seed node's API
+        #[structopt(long)]
+        seed: Option<String>,
+
+        /// Number of scan workers
+        #[structopt(long)]
+        workers: Option<u16>,
+
+        /// Discard the persisted frontier instead of resuming from it
+        #[structopt(long)]
+        fresh: bool,
+
+        /// Keep running once the frontier is empty, periodically requeueing stale nodes [default: rescan.enabled]
+        #[structopt(long)]
+        keep_running: bool,
+    },
+    /// Rescan known nodes with a public API that have not been scanned recently
+    Rescan {
+        /// Minimum time since the last scan, in seconds [default: rescan.min_interval]
+        #[structopt(long)]
+        min_age: Option<u64>,
+
+        /// Number of scan workers
+        #[structopt(long)]
+        workers: Option<u16>,
+    },
+    /// Apply pending schema migrations
+    Migrate,
+    /// Print index statistics
+    Stats,
+    /// Report the agent versions of the crawled nodes
+    Agents,
+    /// List the pin roots whose DAG contains an object, and how many nodes pin each
+    Roots {
+        /// CID of the object
+        cid: String,
+    },
+    /// Report the nodes, edges, public APIs and pins that appeared or vanished between two crawl runs
+    Diff {
+        /// The earlier run [default: the run before --to]
+        #[structopt(long)]
+        from: Option<i64>,
+        /// The later run [default: the latest run]
+        #[structopt(long)]
+        to: Option<i64>,
+        /// Only print the counts
+        #[structopt(long)]
+        summary: bool,
+    },
+    /// Serve the read-only HTTP API over the index
+    Serve {
+        /// Address to listen on [default: api.listen]
+        #[structopt(long)]
+        listen: Option<String>,
+    },
+    /// Export the index to files
+    Export(ExportCommand),
+}
+
+#[derive(StructOpt)]
+pub enum ExportCommand {
+    /// Export the peer graph as GraphML, GEXF or Graphviz DOT
+    Graph {
+        /// graphml, gexf or dot
+        #[structopt(long, default_value = "graphml")]
+        format: GraphFormat,
+
+        /// Edges to include by whether they are active now: active, inactive or all [default: active, or all with
+        /// --at]
+        #[structopt(long)]
+        edges: Option<EdgeFilter>,
+
+        /// Export the graph as it was at this time, e.g. 2024-01-31T00:00:00Z, from each node's last scan before it
+        #[structopt(long)]
+        at: Option<DateTime<Utc>>,
+
+        /// Output file [default: stdout]
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Dump tables to one Parquet or CSV file each, a page at a time
+    Tables {
+        /// Comma-separated tables out of node, node_addr, peer, object and node_object_pin [default: all]
+        #[structopt(long, use_delimiter = true)]
+        tables: Vec<Table>,
+
+        /// parquet or csv
+        #[structopt(long, default_value = "parquet")]
+        format: TableFormat,
+
+        /// Only rows seen since this time, e.g. 2024-01-31T00:00:00Z
+        #[structopt(long)]
+        since: Option<DateTime<Utc>>,
+
+        /// Directory to write <table>.<format> files to
+        #[structopt(short, long, parse(from_os_str), default_value = ".")]
+        output_dir: PathBuf,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub crawl: CrawlConfig,
+    pub probe: ProbeConfig,
+    pub gateway: GatewayConfig,
+    pub dag: DagConfig,
+    pub rescan: RescanConfig,
+    pub api: ApiConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub backend: Backend,
+    // postgres://... or sqlite://...; ignored by the memory backend
+    pub url: String,
+    pub max_connections: u32,
+    // Apply pending migrations whenever the database is opened
+    pub auto_migrate: bool,
+    // Used instead of url for migrations, e.g. to connect as the schema owner
+    pub migrate_url: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+    Memory,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CrawlConfig {
+    pub seed: String,
+    pub workers: u16,
+    pub queue_capacity: usize,
+    // Seconds to wait for a node's API to answer `id`
+    pub node_timeout: u64,
+    // Seconds to wait for each of the calls a scan makes once the node has answered, e.g. pin/ls or swarm/peers,
+    // which take much longer than `id` on large nodes
+    pub api_timeout: u64,
+    // Scans of a queued node before it is marked failed
+    pub max_attempts: i32,
+    // Seconds after which a claimed job with no result is handed to another worker
+    pub claim_timeout: u64,
+    // Seconds to wait for in-flight scans on SIGINT/SIGTERM
+    pub shutdown_timeout: u64,
+    // Concurrent API probes, pin listings/stats, and database writes across all workers
+    pub probe_concurrency: usize,
+    pub pin_concurrency: usize,
+    pub db_concurrency: usize,
+    // Pins stat'd and peers probed at once within one scan, so that a node with millions of pins or peers doesn't
+    // have a future started for every one of them
+    pub node_concurrency: usize,
+    // List indirect pins, i.e. every block below a recursive pin, as well as recursive and direct ones
+    pub indirect_pins: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ProbeConfig {
+    // API ports and schemes tried in order on the host of every peer address, until one answers
+    pub candidates: Vec<ProbeCandidate>,
+    // Concurrent probes against a single host
+    pub host_concurrency: usize,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct GatewayConfig {
+    pub enabled: bool,
+    // Fetched through /ipfs/<cid>. The default is the empty identity CID, which a gateway can serve without
+    // fetching anything.
+    pub cid: String,
+    // Ports and schemes checked on the host of every peer address; every one that answers is recorded
+    pub candidates: Vec<ProbeCandidate>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DagConfig {
+    // Follow links down from every pin root and record them in object_link
+    pub enabled: bool,
+    // Levels below the root to follow
+    pub max_depth: u32,
+    // Blocks recorded per root, the root included
+    pub max_objects: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub listen: String,
+    // Rows per page when a request doesn't give ?limit=, and the most it may ask for
+    pub page_size: i64,
+    pub max_page_size: i64,
+    // Seconds /stats is served from the last read before it is counted again, since it counts whole tables
+    pub stats_ttl: u64,
+    // Deepest GraphQL selection allowed, with the graphql feature. Each hop from a node to its peers or pins and
+    // back to a node is four levels (connection, edges, node, field).
+    pub graphql_max_depth: usize,
+    // Costliest GraphQL query allowed. Each field costs 1, and a connection field costs its selection once per row
+    // of the page it asks for.
+    pub graphql_max_complexity: usize,
+}
+
+// Written "<port>/<scheme>", e.g. "5001/http". The port can also be "swarm", for the port of the peer address
+// itself.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct ProbeCandidate {
+    // None for the swarm port
+    pub port: Option<u16>,
+    pub https: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RescanConfig {
+    // Off by default, so that `crawl` exits once the frontier is empty
+    pub enabled: bool,
+    // Seconds between checks for stale nodes
+    pub check_interval: u64,
+    // Seconds that must pass after a node's last scan before it is scanned again
+    pub min_interval: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: Backend::Postgres,
+            url: "postgres://localhost/db0".to_owned(),
+            max_connections: 20,
+            auto_migrate: false,
+            migrate_url: None,
+        }
+    }
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        CrawlConfig {
+            seed: "/ip4/127.0.0.1/tcp/5001/http".to_owned(),
+            workers: 64,
+            queue_capacity: 128,
+            node_timeout: 3,
+            api_timeout: 60,
+            max_attempts: 3,
+            claim_timeout: 1800,
+            shutdown_timeout: 30,
+            probe_concurrency: 128,
+            pin_concurrency: 64,
+            node_concurrency: 16,
+            db_concurrency: 16,
+            indirect_pins: false,
+        }
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        ProbeConfig {
+            candidates: vec![ProbeCandidate { port: Some(5001), https: false }],
+            host_concurrency: 2,
+        }
+    }
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        GatewayConfig {
+            enabled: true,
+            cid: "bafkqaaa".to_owned(),
+            candidates: vec![
+                ProbeCandidate { port: Some(8080), https: false },
+                ProbeCandidate { port: Some(80), https: false },
+                ProbeCandidate { port: Some(443), https: true },
+            ],
+        }
+    }
+}
+
+impl Default for DagConfig {
+    fn default() -> Self {
+        DagConfig {
+            enabled: false,
+            max_depth: 3,
+            max_objects: 1000,
+        }
+    }
+}
+
+impl Default for RescanConfig {
+    fn default() -> Self {
+        RescanConfig {
+            enabled: false,
+            check_interval: 300,
+            min_interval: 86400,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            listen: "127.0.0.1:8081".to_owned(),
+            page_size: 100,
+            max_page_size: 1000,
+            stats_ttl: 30,
+            graphql_max_depth: 16,
+            graphql_max_complexity: 10000,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(anyhow!("unknown database backend '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for ProbeCandidate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (port, scheme) = match s.split_once('/') {
+            Some(v) => v,
+            None => return Err(anyhow!("probe candidate '{}' isn't <port>/<scheme>", s)),
+        };
+
+        let port = match port {
+            "swarm" => None,
+            _ => Some(port.parse().map_err(|e| anyhow!("invalid port in probe candidate '{}': {}", s, e))?),
+        };
+
+        let https = match scheme {
+            "http" => false,
+            "https" => true,
+            _ => return Err(anyhow!("unknown scheme in probe candidate '{}'", s)),
+        };
+
+        Ok(ProbeCandidate { port, https })
+    }
+}
+
+impl TryFrom<String> for ProbeCandidate {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ProbeCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+
+        match self.port {
+            Some(port) => write!(f, "{}/{}", port, scheme),
+            None => write!(f, "swarm/{}", scheme),
+        }
+    }
+}
+
+impl ApiConfig {
+    pub fn stats_ttl(&self) -> Duration {
+        Duration::from_secs(self.stats_ttl)
+    }
+}
+
+impl CrawlConfig {
+    pub fn node_timeout(&self) -> Duration {
+        Duration::from_secs(self.node_timeout)
+    }
+
+    pub fn api_timeout(&self) -> Duration {
+        Duration::from_secs(self.api_timeout)
+    }
+
+    pub fn claim_timeout(&self) -> Duration {
+        Duration::from_secs(self.claim_timeout)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+impl RescanConfig {
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_secs(self.check_interval)
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_secs(self.min_interval)
+    }
+}
+
+impl Config {
+    // Reads the config file, if there is one, then applies IPFSI_* environment overrides.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+        let mut config = match path {
+            Some(path) => Config::read(path)?,
+            None => {
+                let path = Path::new(DEFAULT_CONFIG_PATH);
+                if path.exists() {
+                    Config::read(path)?
+                } else {
+                    Config::default()
+                }
+            }
+        };
+
+        config.apply_env()?;
+
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Config> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+        toml::from_str(&raw)
+            .with_context(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        env_override("IPFSI_DATABASE_BACKEND", &mut self.database.backend)?;
+        env_override("IPFSI_DATABASE_URL", &mut self.database.url)?;
+        env_override("IPFSI_DATABASE_MAX_CONNECTIONS", &mut self.database.max_connections)?;
+        env_override("IPFSI_DATABASE_AUTO_MIGRATE", &mut self.database.auto_migrate)?;
+        if let Ok(v) = env::var("IPFSI_DATABASE_MIGRATE_URL") {
+            self.database.migrate_url = Some(v);
+        }
+
+        env_override("IPFSI_CRAWL_SEED", &mut self.crawl.seed)?;
+        env_override("IPFSI_CRAWL_WORKERS", &mut self.crawl.workers)?;
+        env_override("IPFSI_CRAWL_QUEUE_CAPACITY", &mut self.crawl.queue_capacity)?;
+        env_override("IPFSI_CRAWL_NODE_TIMEOUT", &mut self.crawl.node_timeout)?;
+        env_override("IPFSI_CRAWL_API_TIMEOUT", &mut self.crawl.api_timeout)?;
+        env_override("IPFSI_CRAWL_MAX_ATTEMPTS", &mut self.crawl.max_attempts)?;
+        env_override("IPFSI_CRAWL_CLAIM_TIMEOUT", &mut self.crawl.claim_timeout)?;
+        env_override("IPFSI_CRAWL_SHUTDOWN_TIMEOUT", &mut self.crawl.shutdown_timeout)?;
+        env_override("IPFSI_CRAWL_PROBE_CONCURRENCY", &mut self.crawl.probe_concurrency)?;
+        env_override("IPFSI_CRAWL_PIN_CONCURRENCY", &mut self.crawl.pin_concurrency)?;
+        env_override("IPFSI_CRAWL_NODE_CONCURRENCY", &mut self.crawl.node_concurrency)?;
+        env_override("IPFSI_CRAWL_DB_CONCURRENCY", &mut self.crawl.db_concurrency)?;
+        env_override("IPFSI_CRAWL_INDIRECT_PINS", &mut self.crawl.indirect_pins)?;
+
+        env_override_list("IPFSI_PROBE_CANDIDATES", &mut self.probe.candidates)?;
+        env_override("IPFSI_PROBE_HOST_CONCURRENCY", &mut self.probe.host_concurrency)?;
+
+        env_override("IPFSI_GATEWAY_ENABLED", &mut self.gateway.enabled)?;
+        env_override("IPFSI_GATEWAY_CID", &mut self.gateway.cid)?;
+        env_override_list("IPFSI_GATEWAY_CANDIDATES", &mut self.gateway.candidates)?;
+
+        env_override("IPFSI_DAG_ENABLED", &mut self.dag.enabled)?;
+        env_override("IPFSI_DAG_MAX_DEPTH", &mut self.dag.max_depth)?;
+        env_override("IPFSI_DAG_MAX_OBJECTS", &mut self.dag.max_objects)?;
+
+        env_override("IPFSI_RESCAN_ENABLED", &mut self.rescan.enabled)?;
+        env_override("IPFSI_RESCAN_CHECK_INTERVAL", &mut self.rescan.check_interval)?;
+        env_override("IPFSI_RESCAN_MIN_INTERVAL", &mut self.rescan.min_interval)?;
+
+        env_override("IPFSI_API_LISTEN", &mut self.api.listen)?;
+        env_override("IPFSI_API_PAGE_SIZE", &mut self.api.page_size)?;
+        env_override("IPFSI_API_MAX_PAGE_SIZE", &mut self.api.max_page_size)?;
+        env_override("IPFSI_API_STATS_TTL", &mut self.api.stats_ttl)?;
+        env_override("IPFSI_API_GRAPHQL_MAX_DEPTH", &mut self.api.graphql_max_depth)?;
+        env_override("IPFSI_API_GRAPHQL_MAX_COMPLEXITY", &mut self.api.graphql_max_complexity)?;
+
+        Ok(())
+    }
+}
+
+fn env_override<T>(key: &str, target: &mut T) -> anyhow::Result<()>
+    where T: FromStr,
+          T::Err: Display {
+    if let Ok(v) = env::var(key) {
+        *target = v.parse().map_err(|e| anyhow!("invalid value for {}: {}", key, e))?;
+    }
+
+    Ok(())
+}
+
+// Like env_override, for a comma-separated list.
+fn env_override_list<T>(key: &str, target: &mut Vec<T>) -> anyhow::Result<()>
+    where T: FromStr,
+          T::Err: Display {
+    if let Ok(v) = env::var(key) {
+        *target = v.split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow!("invalid value for {}: {}", key, e))?;
+    }
+
+    Ok(())
+}
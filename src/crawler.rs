@@ -0,0 +1,1219 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use async_channel::{Receiver, Sender};
+use chrono::Utc;
+use dashmap::{DashMap, DashSet};
+use futures::{StreamExt, TryStreamExt};
+use ipfs_api_backend_hyper::{IpfsApi, IpfsClient, TryFromUri};
+use ipfs_api_backend_hyper::response::IdResponse;
+use reqwest::header::LOCATION;
+use serde::Deserialize;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::cid::{self, Cid};
+use crate::config::{Config, DagConfig, GatewayConfig, ProbeCandidate, ProbeConfig};
+use crate::db::schema::{CrawlJob, FrontierAddr, Node, NodeAddr, NodeGateway, NodeIdMismatch, NodeIdentity, NodeObjectPin, NodeUpdate, Object, ObjectLink, Peer, ScanBatch, ScanError};
+use crate::db::store::Store;
+use crate::error::CrawlError;
+use crate::multiaddr::{Multiaddr, Protocol};
+
+// Shared by every task of a crawl. Nothing in here needs a lock to be read; the mutable parts are atomics or
+// concurrent collections.
+struct Data {
+    db: Arc<dyn Store>,
+    // The crawl_run every scan is recorded under
+    id_run: i64,
+    to_scan_tx: Sender<CrawlJob>,
+    to_scan_rx: Receiver<CrawlJob>,
+    node_timeout: Duration,
+    api_timeout: Duration,
+    max_attempts: i32,
+    indirect_pins: bool,
+    shutdown: AtomicBool,
+    // Swarm addresses this process has written to crawl_queue, so they needn't be checked against it again
+    seen: DashSet<String>,
+    // crawl_queue addresses of the jobs currently being scanned
+    in_flight: DashSet<String>,
+    counters: Counters,
+    // Independent limits for probing APIs, listing and statting pins, and writing to the database
+    probe_limit: Semaphore,
+    pin_limit: Semaphore,
+    db_limit: Semaphore,
+    // Pins stat'd and peers probed at once within a scan
+    node_concurrency: usize,
+    probe: ProbeConfig,
+    // Limits for the hosts currently being probed, so no host gets more than probe.host_concurrency at once
+    host_limits: DashMap<String, Arc<Semaphore>>,
+    gateway: GatewayConfig,
+    http: reqwest::Client,
+    dag: DagConfig,
+}
+
+impl Data {
+    fn new(config: &Config, db: Arc<dyn Store>, id_run: i64) -> anyhow::Result<Data> {
+        let (to_scan_tx, to_scan_rx) = async_channel::bounded(config.crawl.queue_capacity);
+
+        // Gateway checks look at redirects themselves, and most gateways on a bare IP can't have a valid certificate.
+        let http = reqwest::Client::builder()
+            .timeout(config.crawl.node_timeout())
+            .redirect(reqwest::redirect::Policy::none())
+            .danger_accept_invalid_certs(true)
+            .build()?;
+
+        Ok(Data {
+            db,
+            id_run,
+            to_scan_tx,
+            to_scan_rx,
+            node_timeout: config.crawl.node_timeout(),
+            api_timeout: config.crawl.api_timeout(),
+            max_attempts: config.crawl.max_attempts,
+            indirect_pins: config.crawl.indirect_pins,
+            shutdown: AtomicBool::new(false),
+            seen: DashSet::new(),
+            in_flight: DashSet::new(),
+            counters: Counters::default(),
+            probe_limit: Semaphore::new(config.crawl.probe_concurrency),
+            pin_limit: Semaphore::new(config.crawl.pin_concurrency),
+            db_limit: Semaphore::new(config.crawl.db_concurrency),
+            node_concurrency: config.crawl.node_concurrency,
+            probe: config.probe.clone(),
+            host_limits: DashMap::new(),
+            gateway: config.gateway.clone(),
+            http,
+            dag: config.dag.clone(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    scanned: AtomicU64,
+    failed: AtomicU64,
+    released: AtomicU64,
+}
+
+pub struct NodeData {
+    pub client: IpfsClient,
+    pub addr: String,
+    pub info: IdResponse,
+}
+
+pub async fn get_node(addr: &str, node_timeout: Duration) -> Result<NodeData, CrawlError> {
+    let client = IpfsClient::from_multiaddr_str(addr).map_err(|e| CrawlError::MalformedAddr {
+        addr: addr.to_owned(),
+        message: e.to_string(),
+    })?;
+
+    match timeout(node_timeout, client.id(None)).await {
+        Ok(v) => match v {
+            Ok(v) => Ok(NodeData {
+                client,
+                addr: addr.to_owned(),
+                info: v,
+            }),
+            Err(e) => Err(CrawlError::Unreachable {
+                addr: addr.to_owned(),
+                message: e.to_string(),
+            }),
+        }
+        Err(_) => Err(CrawlError::Timeout {
+            addr: addr.to_owned(),
+            after: node_timeout,
+        }),
+    }
+}
+
+fn identity(info: &IdResponse) -> NodeIdentity {
+    let mut protocols = info.protocols.clone();
+    protocols.sort();
+
+    NodeIdentity {
+        id_node: info.id.clone(),
+        agent_version: info.agent_version.clone(),
+        protocol_version: info.protocol_version.clone(),
+        public_key: info.public_key.clone(),
+        protocols,
+        addresses: info.addresses.clone(),
+    }
+}
+
+fn api_error(node: &NodeData, call: &'static str, e: impl fmt::Display) -> CrawlError {
+    CrawlError::Api {
+        addr: node.addr.clone(),
+        call,
+        message: e.to_string(),
+    }
+}
+
+// Records a failure against the node in scan_error. Failing to record it isn't worth failing anything else over.
+async fn record_error(data: &Data, id_node: &str, addr: &str, error: &CrawlError) {
+    println!("! {}", error);
+
+    let _permit = data.db_limit.acquire().await.unwrap();
+
+    let r = data.db.add_scan_error(&ScanError {
+        id_node: id_node.to_owned(),
+        addr: addr.to_owned(),
+        kind: error.kind().to_owned(),
+        message: error.to_string(),
+        occurred: Utc::now(),
+        id_run: data.id_run,
+    }).await;
+
+    if let Err(e) = r {
+        println!("! {}", e);
+    }
+}
+
+// Runs an API call on the node, giving up after crawl.api_timeout so that a stalled node can't hold a worker forever.
+async fn api_call<T, E: fmt::Display>(
+    data: &Data,
+    node: &NodeData,
+    call: &'static str,
+    f: impl Future<Output = Result<T, E>>,
+) -> Result<T, CrawlError> {
+    match timeout(data.api_timeout, f).await {
+        Ok(v) => v.map_err(|e| api_error(node, call, e)),
+        Err(_) => Err(CrawlError::Timeout {
+            addr: node.addr.clone(),
+            after: data.api_timeout,
+        }),
+    }
+}
+
+async fn probe_node(data: &Data, addr: &str) -> Result<NodeData, CrawlError> {
+    let _permit = data.probe_limit.acquire().await.unwrap();
+
+    get_node(addr, data.node_timeout).await
+}
+
+// Runs f while holding one of the host's probe.host_concurrency permits.
+async fn with_host_limit<F: Future>(data: &Data, host: &str, f: F) -> F::Output {
+    let limit = data.host_limits.entry(host.to_owned())
+        .or_insert_with(|| Arc::new(Semaphore::new(data.probe.host_concurrency)))
+        .clone();
+
+    let r = {
+        let _permit = limit.acquire().await.unwrap();
+        f.await
+    };
+
+    // Forget the host once nobody is probing it, so the map doesn't grow with every host ever seen.
+    drop(limit);
+    data.host_limits.remove_if(host, |_, v| Arc::strong_count(v) == 1);
+
+    r
+}
+
+// The API addresses to probe for a peer seen at a swarm address, one per probe candidate, in order. Relayed and
+// link-local addresses don't reach the peer directly, and /dnsaddr needs a TXT lookup first.
+fn api_candidates(probe: &ProbeConfig, addr: &Multiaddr) -> Vec<(ProbeCandidate, String)> {
+    if addr.is_relay() || addr.zone().is_some() {
+        return Vec::new();
+    }
+
+    let host = match addr.host_protocol() {
+        None | Some(Protocol::DnsAddr(_)) => return Vec::new(),
+        Some(v) => v,
+    };
+
+    probe.candidates.iter()
+        .filter_map(|candidate| {
+            let port = candidate.port.or_else(|| addr.port())?;
+            let scheme = if candidate.https { "https" } else { "http" };
+
+            Some((*candidate, format!("{}/tcp/{}/{}", host, port, scheme)))
+        })
+        .collect()
+}
+
+// Base URLs to check for a gateway on the host of a peer seen at a swarm address, one per gateway candidate.
+fn gateway_candidates(gateway: &GatewayConfig, addr: &Multiaddr) -> Vec<String> {
+    if !gateway.enabled || addr.is_relay() || addr.zone().is_some() {
+        return Vec::new();
+    }
+
+    let host = match url_host(addr) {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+
+    gateway.candidates.iter()
+        .filter_map(|candidate| {
+            let port = candidate.port.or_else(|| addr.port())?;
+            let scheme = if candidate.https { "https" } else { "http" };
+
+            Some(format!("{}://{}:{}", scheme, host, port))
+        })
+        .collect()
+}
+
+// The host of addr as written in a URL
+fn url_host(addr: &Multiaddr) -> Option<String> {
+    match addr.host_protocol()? {
+        Protocol::Ip4(a) => Some(a.to_string()),
+        Protocol::Ip6(a) => Some(format!("[{}]", a)),
+        Protocol::Dns(a) | Protocol::Dns4(a) | Protocol::Dns6(a) => Some(a.clone()),
+        _ => None,
+    }
+}
+
+// The base URL of the API at an API address, e.g. /ip4/192.0.2.1/tcp/5001/http
+fn api_url(addr: &str) -> Result<String, CrawlError> {
+    let malformed = |message: &str| CrawlError::MalformedAddr {
+        addr: addr.to_owned(),
+        message: message.to_owned(),
+    };
+
+    let parsed: Multiaddr = addr.parse().map_err(|e: String| malformed(&e))?;
+    let host = url_host(&parsed).ok_or_else(|| malformed("no host"))?;
+    let port = parsed.port().ok_or_else(|| malformed("no port"))?;
+    let scheme = if parsed.protocols.contains(&Protocol::Https) { "https" } else { "http" };
+
+    Ok(format!("{}://{}:{}", scheme, host, port))
+}
+
+// Fetches the well-known CID through the gateway at url. Returns the latency in milliseconds and whether it is a
+// subdomain gateway, or None if it isn't a working gateway.
+async fn check_gateway(data: &Data, url: &str) -> Option<(i32, bool)> {
+    let _permit = data.probe_limit.acquire().await.unwrap();
+
+    let started = Instant::now();
+    let response = data.http.get(&format!("{}/ipfs/{}", url, data.gateway.cid)).send().await.ok()?;
+    let latency_ms = started.elapsed().as_millis() as i32;
+
+    if response.status().is_success() {
+        return Some((latency_ms, false));
+    }
+
+    // Subdomain gateways redirect /ipfs/<cid> to <cid>.ipfs.<host>.
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    if response.status().is_redirection() && location.contains(".ipfs.") {
+        return Some((latency_ms, true));
+    }
+
+    None
+}
+
+async fn check_gateways(data: &Data, batch: &Mutex<ScanBatch>, id: &str, host: &str, urls: Vec<String>) {
+    if urls.is_empty() {
+        return;
+    }
+
+    let results = futures::future::join_all(urls.iter()
+        .map(|url| with_host_limit(data, host, check_gateway(data, url))))
+        .await;
+
+    let mut batch = batch.lock().unwrap();
+    batch.gateways_checked.push(id.to_owned());
+
+    for (url, result) in urls.into_iter().zip(results) {
+        if let Some((latency_ms, subdomain)) = result {
+            println!("+ gateway {}", url);
+
+            batch.gateways.push(NodeGateway {
+                id_node: id.to_owned(),
+                url,
+                latency_ms,
+                subdomain,
+            });
+        }
+    }
+}
+
+async fn scan_node_2(data: &Data, batch: &Mutex<ScanBatch>, id: &str, addr: &str) -> Result<Multiaddr, CrawlError> {
+    let parsed: Multiaddr = addr.parse().map_err(|message| CrawlError::MalformedAddr {
+        addr: addr.to_owned(),
+        message,
+    })?;
+
+    let candidates = api_candidates(&data.probe, &parsed);
+    let gateways = gateway_candidates(&data.gateway, &parsed);
+    if candidates.is_empty() && gateways.is_empty() {
+        println!("* {}", addr);
+        return Ok(parsed);
+    }
+
+    let host = parsed.host().unwrap_or_default();
+
+    let existing = match data.db.get_node(id).await? {
+        None => None,
+        Some(v) => if v.id == id {
+            Some(v)
+        } else {
+            None
+        }
+    };
+
+    // Most peers don't expose their API, so failing to probe one is the normal case rather than an error.
+    let probe_api = async {
+        for (candidate, api_addr) in candidates {
+            if let Ok(v) = with_host_limit(data, &host, probe_node(data, &api_addr)).await {
+                return Some((candidate, api_addr, v));
+            }
+        }
+
+        None
+    };
+
+    let (found, ()) = tokio::join!(
+        probe_api,
+        check_gateways(data, batch, id, &host, gateways),
+    );
+
+    // Behind NAT or a reverse proxy the API at a peer's host can belong to another node. The address then goes to
+    // the node that answered, and the advertised one is treated as not exposing its API.
+    let found = match found {
+        Some((candidate, public_addr, v)) if v.info.id != id => {
+            println!("~ {} answered as {}, not {}", public_addr, v.info.id, id);
+
+            {
+                let mut batch = batch.lock().unwrap();
+
+                batch.id_mismatches.push(NodeIdMismatch {
+                    id_advertised: id.to_owned(),
+                    id_reported: v.info.id.clone(),
+                    api_addr: public_addr.clone(),
+                    addr: addr.to_owned(),
+                });
+                batch.identities.push(identity(&v.info));
+                batch.nodes.push(Node {
+                    id: v.info.id.clone(),
+                    seen_first: Utc::now(),
+                    seen_last: Utc::now(),
+                    scan_last: None,
+                    public_addr: Some(public_addr.clone()),
+                    api_candidate: Some(candidate.to_string()),
+                });
+            }
+
+            // The swarm address is the advertised node's, so the API address is the queue key.
+            batch.lock().unwrap().frontier.push(FrontierAddr {
+                addr: public_addr.clone(),
+                id_node: v.info.id.clone(),
+                api_addr: Some(public_addr),
+            });
+
+            None
+        }
+        found => found,
+    };
+
+    match found {
+        None => {
+            println!("  {} [{}]", addr, data.to_scan_rx.len());
+
+            let mut batch = batch.lock().unwrap();
+
+            match existing {
+                None => {
+                    // This node has never been seen before. Add it.
+                    batch.nodes.push(Node {
+                        id: id.to_owned(),
+                        seen_first: Utc::now(),
+                        seen_last: Utc::now(),
+                        scan_last: None,
+                        public_addr: None,
+                        api_candidate: None,
+                    });
+                }
+                Some(v) => match v.public_addr {
+                    None => {
+                        // This node has been seen before, but it didn't have a public address. Only update seen_last.
+                        batch.node_updates.push(NodeUpdate {
+                            id: id.to_owned(),
+                            seen_last: v.seen_last,
+                            scan_last: v.scan_last,
+                            public_addr: None,
+                            api_candidate: None,
+                        });
+                    }
+                    Some(_) => {
+                        // This node has been seen before with a public address, but it is not inaccessible. Delete the public address.
+                        batch.node_updates.push(NodeUpdate {
+                            id: id.to_owned(),
+                            seen_last: v.seen_last,
+                            scan_last: v.scan_last,
+                            public_addr: None,
+                            api_candidate: None,
+                        });
+                    }
+                }
+            }
+
+            Ok(parsed)
+        }
+        Some((candidate, public_addr, v)) => {
+            println!("+ {} ({})", public_addr, candidate);
+
+            {
+                let mut batch = batch.lock().unwrap();
+
+                batch.identities.push(identity(&v.info));
+
+                match existing {
+                    None => {
+                        // This node has never been seen before. Add it.
+                        batch.nodes.push(Node {
+                            id: id.to_owned(),
+                            seen_first: Utc::now(),
+                            seen_last: Utc::now(),
+                            scan_last: None,
+                            public_addr: Some(public_addr.clone()),
+                            api_candidate: Some(candidate.to_string()),
+                        });
+                    }
+                    Some(v) => {
+                        // This node had been seen before. Update seen_last.
+                        batch.node_updates.push(NodeUpdate {
+                            id: id.to_owned(),
+                            seen_last: v.seen_last,
+                            scan_last: v.scan_last,
+                            public_addr: Some(public_addr.clone()),
+                            api_candidate: Some(candidate.to_string()),
+                        });
+                    }
+                }
+            }
+
+            // Queue it in the frontier; whichever worker claims it probes it again before scanning.
+            batch.lock().unwrap().frontier.push(FrontierAddr {
+                addr: addr.to_owned(),
+                id_node: v.info.id.clone(),
+                api_addr: Some(public_addr),
+            });
+
+            Ok(parsed)
+        }
+    }
+}
+
+async fn read_node_objects(data: &Data, batch: &Mutex<ScanBatch>, node: &NodeData) -> Result<(), CrawlError> {
+    // "all" includes indirect pins. Without them, recursive and direct pins have to be listed separately.
+    let types: &[&str] = if data.indirect_pins { &["all"] } else { &["recursive", "direct"] };
+
+    let mut pins = Vec::new();
+    for &typ in types {
+        let _permit = data.pin_limit.acquire().await.unwrap();
+
+        let r = api_call(data, node, "pin/ls", node.client.pin_ls(None, Some(typ))).await?;
+        pins.extend(r.keys.into_iter().map(|(id, v)| (id, v.typ)));
+    }
+
+    batch.lock().unwrap().pins_indirect = data.indirect_pins;
+
+    futures::stream::iter(pins)
+        .for_each_concurrent(data.node_concurrency, |(id, pin_type)| async move {
+            let indirect = pin_type == "indirect";
+            let path = format!("/ipfs/{}", id);
+
+            let stat = {
+                let _permit = data.pin_limit.acquire().await.unwrap();
+
+                api_call(data, node, "object/stat", node.client.object_stat(&path)).await
+            };
+
+            // The same content can be pinned as v0 on one node and v1 on another; both become the same object.
+            let parsed: Option<Cid> = id.parse().ok();
+            let id = parsed.as_ref().map(|v| v.to_string()).unwrap_or(id);
+
+            // A pin that can't be stat'd, e.g. because the node no longer has the block, is recorded rather than
+            // failing the rest of the scan. It is still listed, so it isn't taken for unpinned.
+            let stat = match stat {
+                Ok(v) => v,
+                Err(e) => {
+                    record_error(data, &node.info.id, &node.addr, &e).await;
+
+                    batch.lock().unwrap().pins_without_stat.push(NodeObjectPin {
+                        id_node: node.info.id.clone(),
+                        id_object: id,
+                        pin_type,
+                    });
+                    return;
+                }
+            };
+
+            // files/stat fails for anything that isn't UnixFS, which is fine. Indirect pins are parts of recursive
+            // ones, so only their object/stat is worth the calls.
+            let file = if indirect {
+                None
+            } else {
+                let _permit = data.pin_limit.acquire().await.unwrap();
+
+                api_call(data, node, "files/stat", node.client.files_stat(&path)).await.ok()
+            };
+
+            {
+                let mut batch = batch.lock().unwrap();
+
+                batch.pins.push(NodeObjectPin {
+                    id_node: node.info.id.clone(),
+                    id_object: id.clone(),
+                    pin_type,
+                });
+                batch.objects.push(Object {
+                    id: id.clone(),
+                    cid_version: parsed.as_ref().map(|v| v.version as i16),
+                    codec: parsed.as_ref().map(|v| v.codec_name()),
+                    hash_function: parsed.as_ref().map(|v| v.hash_function_name()),
+                    size: stat.data_size as i64,
+                    cumulative_size: stat.cumulative_size as i64,
+                    num_links: stat.num_links as i64,
+                    block_size: stat.block_size as i64,
+                    links_size: stat.links_size as i64,
+                    file_type: file.as_ref().map(|v| v.typ.clone()),
+                    file_size: file.as_ref().map(|v| v.size as i64),
+                    file_blocks: file.as_ref().map(|v| v.blocks as i64),
+                });
+            }
+
+            if data.dag.enabled && !indirect {
+                walk_dag(data, batch, node, &id).await;
+            }
+        })
+        .await;
+
+    Ok(())
+}
+
+// Follows links down from a pin root, breadth first, up to dag.max_depth levels and dag.max_objects blocks. A block
+// that can't be listed, e.g. a raw leaf or one the node doesn't have locally, just isn't followed any further.
+async fn walk_dag(data: &Data, batch: &Mutex<ScanBatch>, node: &NodeData, root: &str) {
+    let mut visited = HashSet::new();
+    visited.insert(root.to_owned());
+    let mut level = vec![root.to_owned()];
+
+    for _ in 0..data.dag.max_depth {
+        let listed = futures::future::join_all(level.iter().map(|id| async move {
+            let _permit = data.pin_limit.acquire().await.unwrap();
+
+            // Listing a block the node doesn't have makes it go looking for it in the swarm.
+            match timeout(data.api_timeout, node.client.object_links(id)).await {
+                Ok(Ok(v)) => Some((id, v.links)),
+                _ => None,
+            }
+        })).await;
+
+        let mut next = Vec::new();
+        {
+            let mut batch = batch.lock().unwrap();
+
+            for (id_parent, links) in listed.into_iter().flatten() {
+                for link in links {
+                    let id_child = cid::normalize(&link.hash);
+
+                    if !visited.contains(&id_child) {
+                        if visited.len() >= data.dag.max_objects {
+                            continue;
+                        }
+
+                        visited.insert(id_child.clone());
+                        next.push(id_child.clone());
+                    }
+
+                    batch.links.push(ObjectLink {
+                        id_parent: id_parent.clone(),
+                        id_child,
+                        name: link.name,
+                    });
+                }
+            }
+        }
+
+        if next.is_empty() {
+            break;
+        }
+        level = next;
+    }
+}
+
+async fn read_node_peers(data: &Data, batch: &Mutex<ScanBatch>, node: &NodeData) -> Result<(), CrawlError> {
+    let url = api_url(&node.addr)?;
+    let r = api_call(data, node, "swarm/peers", swarm_peers(data, &url)).await?;
+
+    add_peers(data, batch, connections(&node.info.id, r)).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SwarmPeers {
+    // null when the node has no peers
+    peers: Option<Vec<SwarmPeer>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SwarmPeer {
+    addr: String,
+    peer: String,
+    #[serde(default)]
+    latency: String,
+    #[serde(default)]
+    muxer: String,
+    // 1 is inbound, 2 outbound
+    #[serde(default)]
+    direction: i32,
+    #[serde(default)]
+    streams: Option<Vec<serde::de::IgnoredAny>>,
+}
+
+// swarm/peers with the details of each connection, which the node only fills in when asked for. The client's
+// swarm_peers() doesn't ask, so the call is made directly.
+async fn swarm_peers(data: &Data, url: &str) -> Result<SwarmPeers, reqwest::Error> {
+    data.http.post(&format!("{}/api/v0/swarm/peers", url))
+        .query(&[("verbose", "true"), ("latency", "true"), ("streams", "true"), ("direction", "true")])
+        .timeout(data.api_timeout)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+fn connections(id_node: &str, r: SwarmPeers) -> Vec<Peer> {
+    r.peers.unwrap_or_default().into_iter()
+        .map(|p| Peer {
+            id_left: id_node.to_owned(),
+            id_right: p.peer,
+            latency_ms: latency_ms(&p.latency),
+            direction: match p.direction {
+                1 => Some("inbound".to_owned()),
+                2 => Some("outbound".to_owned()),
+                _ => None,
+            },
+            muxer: Some(p.muxer).filter(|v| !v.is_empty()),
+            streams: p.streams.map_or(0, |v| v.len() as i32),
+            addr: p.addr,
+        })
+        .collect()
+}
+
+// swarm/peers gives latency as a Go duration, e.g. 23.456ms or 1m2.5s, or n/a when it isn't known.
+fn latency_ms(s: &str) -> Option<i32> {
+    if s.is_empty() {
+        return None;
+    }
+
+    let mut rest = s;
+    let mut total = 0.0;
+
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let value: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3_600_000.0,
+            "m" => 60_000.0,
+            "s" => 1000.0,
+            "ms" => 1.0,
+            "us" | "µs" | "μs" => 0.001,
+            "ns" => 0.000_001,
+            _ => return None,
+        };
+        rest = &rest[unit_end..];
+
+        total += value * scale;
+    }
+
+    Some(total.round() as i32)
+}
+
+// Adds the connections reported by a node to the batch, and queues the peers with a reachable API.
+async fn add_peers(data: &Data, batch: &Mutex<ScanBatch>, peers: Vec<Peer>) -> Result<(), CrawlError> {
+    // Every connection is an edge, but only addresses this process hasn't seen yet need to be checked against the
+    // shared frontier and probed.
+    let mut candidates = HashSet::new();
+    let addrs: Vec<String> = peers.iter()
+        .filter(|v| !data.seen.contains(&v.addr) && candidates.insert(v.addr.as_str()))
+        .map(|v| v.addr.clone())
+        .collect();
+
+    let unseen = {
+        let _permit = data.db_limit.acquire().await.unwrap();
+
+        data.db.get_unseen_addrs(&addrs).await?
+    };
+    let unseen: HashSet<&str> = unseen.iter().map(|v| v.as_str()).collect();
+    let unseen = &unseen;
+
+    // They are marked seen along with the rest of the scan, whether or not probing them works out.
+    batch.lock().unwrap().frontier.extend(peers.iter()
+        .filter(|v| unseen.contains(v.addr.as_str()))
+        .map(|v| FrontierAddr {
+            addr: v.addr.clone(),
+            id_node: v.id_right.clone(),
+            api_addr: None,
+        }));
+
+    // A storage failure fails the whole scan. Anything else is the peer's problem, so it is recorded against the
+    // peer and the rest of the scan goes on.
+    futures::stream::iter(peers.iter().map(Ok::<_, CrawlError>))
+        .try_for_each_concurrent(data.node_concurrency, |peer| async move {
+            let parsed = if unseen.contains(peer.addr.as_str()) {
+                match scan_node_2(data, batch, &peer.id_right, &peer.addr).await {
+                    Ok(v) => v,
+                    Err(CrawlError::Storage(e)) => return Err(CrawlError::Storage(e)),
+                    Err(e) => {
+                        record_error(data, &peer.id_right, &peer.addr, &e).await;
+
+                        return Ok(());
+                    }
+                }
+            } else {
+                // A malformed address was recorded when it was first seen.
+                match peer.addr.parse::<Multiaddr>() {
+                    Ok(v) => v,
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            // The peer may not have been added as a node; write_scan adds a stub row for it if so.
+            let mut batch = batch.lock().unwrap();
+
+            batch.peers.push(peer.clone());
+
+            batch.node_addrs.push(NodeAddr {
+                id_node: peer.id_right.clone(),
+                addr: peer.addr.clone(),
+                transport: parsed.transport().map(|v| v.to_owned()),
+                host: parsed.host(),
+                port: parsed.port().map(i32::from),
+                relay: parsed.is_relay(),
+            });
+
+            Ok(())
+        })
+        .await
+}
+
+async fn scan_job(data: &Data, job: &CrawlJob) -> Result<(), CrawlError> {
+    let node = probe_node(data, &job.api_addr).await?;
+
+    // Everything the scan finds is collected here and written at the end, so a failed scan writes nothing.
+    let mut batch = ScanBatch::new(&node.info.id, data.id_run);
+    batch.api_addr = Some(job.api_addr.clone());
+    batch.identities.push(identity(&node.info));
+
+    // Another node can have taken over the API address since the job was queued. Whatever it pins and peers with
+    // is its own, so the scan goes ahead under the ID it reports.
+    if node.info.id != job.id_node {
+        println!("~ {} answered as {}, not {}", job.api_addr, node.info.id, job.id_node);
+
+        batch.id_mismatches.push(NodeIdMismatch {
+            id_advertised: job.id_node.clone(),
+            id_reported: node.info.id.clone(),
+            api_addr: job.api_addr.clone(),
+            addr: job.addr.clone(),
+        });
+    }
+
+    let batch = Mutex::new(batch);
+
+    tokio::try_join!(
+        read_node_objects(data, &batch, &node),
+        read_node_peers(data, &batch, &node),
+    )?;
+
+    let batch = batch.into_inner().unwrap();
+    let _permit = data.db_limit.acquire().await.unwrap();
+
+    data.db.write_scan(&batch).await?;
+
+    // Only now are the peers in crawl_queue, so that a failed scan leaves them to be checked again.
+    for v in batch.frontier {
+        data.seen.insert(v.addr);
+    }
+
+    Ok(())
+}
+
+async fn node_scan_worker(data: Arc<Data>, i: u16) {
+    // Ends once the channel is closed and drained, either because the frontier is exhausted or on shutdown.
+    while let Ok(job) = data.to_scan_rx.recv().await {
+        if data.shutdown.load(Ordering::SeqCst) {
+            // Don't start new scans while shutting down; hand the job back to the frontier instead.
+            match data.db.release_crawl_jobs(&[job.addr]).await {
+                Ok(n) => { data.counters.released.fetch_add(n, Ordering::SeqCst); }
+                Err(e) => println!("! {}", e),
+            }
+            continue;
+        }
+
+        println!("{} => {} (attempt {})", i, job.api_addr, job.attempts);
+        data.in_flight.insert(job.addr.clone());
+
+        let result = match scan_job(&data, &job).await {
+            Ok(()) => {
+                data.counters.scanned.fetch_add(1, Ordering::SeqCst);
+                data.db.complete_crawl_job(&job.addr).await
+            }
+            Err(e) => {
+                data.counters.failed.fetch_add(1, Ordering::SeqCst);
+                record_error(&data, &job.id_node, &job.api_addr, &e).await;
+                data.db.fail_crawl_job(&job.addr, data.max_attempts).await
+            }
+        };
+
+        if let Err(e) = result {
+            println!("! {}", e);
+        }
+
+        data.in_flight.remove(&job.addr);
+    }
+}
+
+// Moves jobs from the shared crawl_queue table into this process's scan channel. If `exit_when_empty` is
+// set, the channel is closed once no job is pending or claimed by any crawler, which lets the workers exit.
+async fn frontier_feeder(data: Arc<Data>, claim_timeout: Duration, exit_when_empty: bool) {
+    loop {
+        if data.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let free = data.to_scan_tx.capacity().unwrap_or(0).saturating_sub(data.to_scan_tx.len());
+        if free == 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        }
+
+        let jobs = match data.db.claim_crawl_jobs(free as i64).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("! {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        if jobs.is_empty() {
+            match data.db.release_stale_crawl_jobs(claim_timeout).await {
+                Ok(0) => {}
+                Ok(n) => println!("~ released {} stale claims", n),
+                Err(e) => println!("! {}", e),
+            }
+
+            // Jobs being scanned stay claimed until they finish, so no open jobs also means every worker is idle.
+            if exit_when_empty && data.to_scan_tx.is_empty() {
+                match data.db.count_open_crawl_jobs().await {
+                    Ok(0) => {
+                        println!("~ frontier is empty");
+                        data.to_scan_tx.close();
+                        return;
+                    }
+                    Ok(_) => {}
+                    Err(e) => println!("! {}", e),
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+
+        // The channel is only closed on shutdown. Whatever was claimed but not handed to a worker goes back to the
+        // frontier rather than waiting out the claim timeout.
+        let mut unsent = Vec::new();
+        for job in jobs {
+            if !unsent.is_empty() {
+                unsent.push(job.addr);
+            } else if let Err(e) = data.to_scan_tx.send(job).await {
+                unsent.push(e.into_inner().addr);
+            }
+        }
+
+        if !unsent.is_empty() {
+            match data.db.release_crawl_jobs(&unsent).await {
+                Ok(n) => { data.counters.released.fetch_add(n, Ordering::SeqCst); }
+                Err(e) => println!("! {}", e),
+            }
+            return;
+        }
+    }
+}
+
+pub async fn requeue_stale_nodes(db: &dyn Store, min_interval: Duration) -> anyhow::Result<usize> {
+    let mut count = 0;
+
+    for node in db.get_unscanned_nodes(min_interval).await? {
+        let public_addr = match node.public_addr {
+            None => continue,
+            Some(v) => v,
+        };
+
+        db.add_crawl_job(&public_addr, &node.id, &public_addr).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+async fn rescan_scheduler(data: Arc<Data>, check_interval: Duration, min_interval: Duration) {
+    loop {
+        tokio::time::sleep(check_interval).await;
+
+        match requeue_stale_nodes(&data.db, min_interval).await {
+            Ok(n) => println!("~ queued {} stale nodes for rescan", n),
+            Err(e) => println!("! {}", e),
+        }
+    }
+}
+
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        r = tokio::signal::ctrl_c() => r?,
+        _ = sigterm.recv() => {}
+    }
+
+    Ok(())
+}
+
+// kind is recorded on the crawl_run, crawl or rescan
+pub async fn run(config: &Config, db: Arc<dyn Store>, kind: &str, schedule_rescans: bool) -> anyhow::Result<()> {
+    let started = Instant::now();
+
+    let released = db.release_stale_crawl_jobs(config.crawl.claim_timeout()).await?;
+    if released > 0 {
+        println!("Released {} stale claims", released);
+    }
+
+    let id_run = db.start_crawl_run(kind).await?;
+    println!("Started {} run {}", kind, id_run);
+
+    let data = Arc::new(Data::new(config, db, id_run)?);
+
+    let mut handles = Vec::new();
+
+    for i in 0..config.crawl.workers {
+        let d = data.clone();
+        let handle = tokio::spawn(async move {
+            node_scan_worker(d, i).await;
+        });
+        handles.push(handle);
+    }
+
+    if schedule_rescans {
+        let d = data.clone();
+        let check_interval = config.rescan.check_interval();
+        let min_interval = config.rescan.min_interval();
+        tokio::spawn(async move {
+            rescan_scheduler(d, check_interval, min_interval).await;
+        });
+    }
+
+    {
+        let d = data.clone();
+        let claim_timeout = config.crawl.claim_timeout();
+        tokio::spawn(async move {
+            frontier_feeder(d, claim_timeout, !schedule_rescans).await;
+        });
+    }
+
+    let workers = futures::future::join_all(handles);
+    tokio::pin!(workers);
+
+    tokio::select! {
+        _ = &mut workers => {}
+        r = shutdown_signal() => {
+            r?;
+
+            println!("Shutting down; waiting up to {}s for in-flight scans", config.crawl.shutdown_timeout);
+            data.shutdown.store(true, Ordering::SeqCst);
+            data.to_scan_tx.close();
+
+            if timeout(config.crawl.shutdown_timeout(), &mut workers).await.is_err() {
+                println!("Timed out waiting for in-flight scans");
+            }
+        }
+    }
+
+    // Return everything this process claimed but didn't finish to the frontier.
+    let mut unfinished: Vec<String> = data.in_flight.iter().map(|v| v.key().clone()).collect();
+    while let Ok(job) = data.to_scan_rx.try_recv() {
+        unfinished.push(job.addr);
+    }
+    if !unfinished.is_empty() {
+        let n = data.db.release_crawl_jobs(&unfinished).await?;
+        data.counters.released.fetch_add(n, Ordering::SeqCst);
+    }
+
+    data.db.finish_crawl_run(data.id_run).await?;
+
+    // Wait for outstanding queries to complete before exiting.
+    data.db.close().await;
+
+    println!("Scanned {} nodes, {} failed, {} returned to the queue in {}s",
+             data.counters.scanned.load(Ordering::SeqCst),
+             data.counters.failed.load(Ordering::SeqCst),
+             data.counters.released.load(Ordering::SeqCst),
+             started.elapsed().as_secs());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::MemoryStore;
+
+    // Every way scan_node_2 can return without adding the peer as a node, plus a peer that is added but can't
+    // be probed. None of them may leave an edge or address pointing at a missing node.
+    #[tokio::test]
+    async fn peers_that_are_not_added_as_nodes_get_stub_rows() {
+        let mut config = Config::default();
+        config.crawl.node_timeout = 0;
+        config.gateway.enabled = false;
+
+        let db = Arc::new(MemoryStore::default());
+        let id_run = db.start_crawl_run("crawl").await.unwrap();
+        let data = Data::new(&config, db.clone(), id_run).unwrap();
+
+        let peers = vec![
+            // Don't lead to an API address
+            ("QmDnsaddr", "/dnsaddr/bootstrap.libp2p.io"),
+            ("QmCircuit", "/ip4/192.0.2.1/tcp/4001/p2p/QmRelay/p2p-circuit"),
+            ("QmZone", "/ip6zone/eth0/ip6/fe80::1/tcp/4001"),
+            // Only protocols the parser doesn't know. The connection is real, so the peer still gets a node and
+            // an edge.
+            ("QmUnknown", "/ip9/192.0.2.1/tcp/4001"),
+            // Not probed successfully; added without a public address
+            ("QmQuic", "/ip4/192.0.2.1/udp/4001/quic-v1"),
+            // Malformed; recorded in scan_error and skipped
+            ("QmMalformed", "/ip4/192.0.2.300/tcp/4001"),
+        ];
+        let peers: Vec<Peer> = peers.into_iter()
+            .map(|(id, addr)| Peer {
+                id_left: "QmScanner".to_owned(),
+                id_right: id.to_owned(),
+                addr: addr.to_owned(),
+                latency_ms: None,
+                direction: None,
+                muxer: None,
+                streams: 0,
+            })
+            .collect();
+
+        let batch = Mutex::new(ScanBatch::new("QmScanner", id_run));
+        add_peers(&data, &batch, peers).await.unwrap();
+        db.write_scan(&batch.into_inner().unwrap()).await.unwrap();
+
+        for id in &["QmScanner", "QmDnsaddr", "QmCircuit", "QmZone", "QmUnknown", "QmQuic"] {
+            assert!(db.get_node(id).await.unwrap().is_some(), "{} has no node row", id);
+        }
+        assert!(db.get_node("QmMalformed").await.unwrap().is_none());
+
+        let stats = db.get_stats().await.unwrap();
+        assert_eq!(stats.nodes, 6);
+        assert_eq!(stats.peers_active, 5);
+    }
+
+    // The node only reports latency, direction, muxer and streams when every one of them is asked for
+    #[tokio::test]
+    async fn swarm_peers_asks_for_connection_details() {
+        use std::collections::HashMap;
+        use warp::Filter;
+
+        let route = warp::post()
+            .and(warp::path!("api" / "v0" / "swarm" / "peers"))
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|query: HashMap<String, String>| {
+                let verbose = ["verbose", "latency", "streams", "direction"].iter()
+                    .all(|v| query.get(*v).map(|v| v.as_str()) == Some("true"));
+
+                if verbose {
+                    r#"{"Peers": [
+                        {"Addr": "/ip4/192.0.2.1/tcp/4001", "Peer": "QmLeft", "Latency": "23.4ms", "Muxer": "/yamux/1.0.0",
+                         "Direction": 2, "Streams": [{"Protocol": "/ipfs/bitswap/1.2.0"}, {"Protocol": "/ipfs/kad/1.0.0"}]},
+                        {"Addr": "/ip4/192.0.2.2/udp/4001/quic-v1", "Peer": "QmRight", "Latency": "n/a", "Muxer": "",
+                         "Direction": 1, "Streams": null}
+                    ]}"#
+                } else {
+                    r#"{"Peers": [{"Addr": "/ip4/192.0.2.1/tcp/4001", "Peer": "QmLeft", "Latency": "", "Muxer": "", "Direction": 0, "Streams": null}]}"#
+                }
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let config = Config::default();
+        let db = Arc::new(MemoryStore::default());
+        let data = Data::new(&config, db, 1).unwrap();
+
+        let url = api_url(&format!("/ip4/127.0.0.1/tcp/{}/http", addr.port())).unwrap();
+        let peers = connections("QmNode", swarm_peers(&data, &url).await.unwrap());
+
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].id_left, "QmNode");
+        assert_eq!(peers[0].id_right, "QmLeft");
+        assert_eq!(peers[0].latency_ms, Some(23));
+        assert_eq!(peers[0].direction.as_deref(), Some("outbound"));
+        assert_eq!(peers[0].muxer.as_deref(), Some("/yamux/1.0.0"));
+        assert_eq!(peers[0].streams, 2);
+        assert_eq!(peers[1].latency_ms, None);
+        assert_eq!(peers[1].direction.as_deref(), Some("inbound"));
+        assert_eq!(peers[1].muxer, None);
+        assert_eq!(peers[1].streams, 0);
+    }
+
+    #[test]
+    fn api_urls_come_from_api_addresses() {
+        assert_eq!(api_url("/ip4/192.0.2.1/tcp/5001/http").unwrap(), "http://192.0.2.1:5001");
+        assert_eq!(api_url("/ip6/2001:db8::1/tcp/5001/http").unwrap(), "http://[2001:db8::1]:5001");
+        assert_eq!(api_url("/dns4/ipfs.example.com/tcp/443/https").unwrap(), "https://ipfs.example.com:443");
+        assert!(api_url("/dnsaddr/bootstrap.libp2p.io").is_err());
+    }
+
+    // A failed object/stat mustn't read as the node having unpinned the object
+    #[tokio::test]
+    async fn pins_that_fail_stat_stay_active() {
+        let db = MemoryStore::default();
+        let id_run = db.start_crawl_run("crawl").await.unwrap();
+
+        let pin = |id_object: &str| NodeObjectPin {
+            id_node: "QmNode".to_owned(),
+            id_object: id_object.to_owned(),
+            pin_type: "recursive".to_owned(),
+        };
+        let object = |id: &str| Object {
+            id: id.to_owned(),
+            cid_version: None,
+            codec: None,
+            hash_function: None,
+            size: 1,
+            cumulative_size: 1,
+            num_links: 0,
+            block_size: 1,
+            links_size: 0,
+            file_type: None,
+            file_size: None,
+            file_blocks: None,
+        };
+
+        let mut batch = ScanBatch::new("QmNode", id_run);
+        batch.objects = vec![object("bafyStored"), object("bafyOther")];
+        batch.pins = vec![pin("bafyStored"), pin("bafyOther")];
+        db.write_scan(&batch).await.unwrap();
+
+        // bafyStored fails its stat this time, as does bafyNew, which was never stored
+        let mut batch = ScanBatch::new("QmNode", id_run);
+        batch.objects = vec![object("bafyOther")];
+        batch.pins = vec![pin("bafyOther")];
+        batch.pins_without_stat = vec![pin("bafyStored"), pin("bafyNew")];
+        db.write_scan(&batch).await.unwrap();
+
+        let pins: Vec<String> = db.get_node_pins("QmNode", 10, 0).await.unwrap().into_iter()
+            .map(|v| v.id_object)
+            .collect();
+        assert_eq!(pins, vec!["bafyOther", "bafyStored"]);
+        assert!(db.get_object("bafyNew").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,995 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::db::schema::{AgentVersionCount, CrawlJob, CrawlRun, GraphEdge, GraphNode, Node, NodeAddr, NodeAddrRow, NodeGateway, NodeIdMismatch, NodeIdentity, NodeObjectPin, NodeObjectPinRow, NodePin, NodeUpdate, Object, Peer, PeerEdge, PeerRow, RootPins, RunDiffRow, ScanBatch, ScanError, Stats};
+use crate::db::store::Store;
+
+// Keeps everything in process memory. Meant for tests and throwaway crawls; foreign keys are checked the same
+// way Postgres would, so violations surface here too.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    node: HashMap<String, Node>,
+    node_addr: HashMap<(String, String), (NodeAddr, bool)>,
+    // Keyed by (id_node, url); the value is the gateway, its last check and whether it is active
+    node_gateway: HashMap<(String, String), (NodeGateway, DateTime<Utc>, bool)>,
+    // In insertion order, with seen_first and seen_last
+    node_identity: Vec<(NodeIdentity, DateTime<Utc>, DateTime<Utc>)>,
+    // Keyed by (id_advertised, id_reported, api_addr), with seen_first and seen_last
+    node_id_mismatch: HashMap<(String, String, String), (NodeIdMismatch, DateTime<Utc>, DateTime<Utc>)>,
+    // Keyed by (id_left, id_right); the value is the last connection, seen_first, seen_last and whether it is active
+    peer: HashMap<(String, String), (Peer, DateTime<Utc>, DateTime<Utc>, bool)>,
+    // With the time of the observation and its crawl run
+    peer_observation: Vec<(Peer, DateTime<Utc>, i64)>,
+    object: HashMap<String, Object>,
+    // Keyed by (id_node, id_object); the value is the pin type, seen_first, seen_last and whether it is active
+    node_object_pin: HashMap<(String, String), (String, DateTime<Utc>, DateTime<Utc>, bool)>,
+    // (id_parent, id_child, name)
+    object_link: HashSet<(String, String, String)>,
+    crawl_queue: HashMap<String, QueueRow>,
+    scan_error: Vec<ScanError>,
+    crawl_run: Vec<CrawlRun>,
+    // Keyed by (id_run, id_node); the value is the scan time and the API address
+    node_scan: HashMap<(i64, String), (DateTime<Utc>, Option<String>)>,
+    // Keyed by (id_run, id_node, id_object); the value is the pin type
+    pin_observation: HashMap<(i64, String, String), String>,
+}
+
+struct QueueRow {
+    id_node: String,
+    api_addr: Option<String>,
+    state: &'static str,
+    attempts: i32,
+    enqueued: DateTime<Utc>,
+    claimed: Option<DateTime<Utc>>,
+}
+
+impl Tables {
+    // Upserts the object, keeping the highest CID version it has been seen under
+    fn insert_object(&mut self, object: &Object) {
+        let cid_version = self.object.get(&object.id).and_then(|v| v.cid_version).max(object.cid_version);
+
+        self.object.insert(object.id.clone(), Object {
+            cid_version,
+            ..object.clone()
+        });
+    }
+
+    fn add_crawl_seen(&mut self, addr: &str, id_node: &str) {
+        if !self.crawl_queue.contains_key(addr) {
+            self.crawl_queue.insert(addr.to_owned(), QueueRow {
+                id_node: id_node.to_owned(),
+                api_addr: None,
+                state: "seen",
+                attempts: 0,
+                enqueued: Utc::now(),
+                claimed: None,
+            });
+        }
+    }
+
+    // Queues the address unless it is already pending or claimed
+    fn add_crawl_job(&mut self, addr: &str, id_node: &str, api_addr: &str) {
+        if let Some(v) = self.crawl_queue.get(addr) {
+            if v.state == "pending" || v.state == "claimed" {
+                return;
+            }
+        }
+
+        self.crawl_queue.insert(addr.to_owned(), QueueRow {
+            id_node: id_node.to_owned(),
+            api_addr: Some(api_addr.to_owned()),
+            state: "pending",
+            attempts: 0,
+            enqueued: Utc::now(),
+            claimed: None,
+        });
+    }
+
+    fn check_scan(&self, batch: &ScanBatch) -> anyhow::Result<()> {
+        if !self.crawl_run.iter().any(|v| v.id == batch.id_run) {
+            return Err(anyhow!("insert or update on table \"node_scan\" violates foreign key constraint: crawl run {} does not exist",
+                               batch.id_run));
+        }
+
+        let stubs = batch.referenced_nodes();
+        let added: HashSet<&str> = std::iter::once(batch.id_node.as_str())
+            .chain(batch.nodes.iter().map(|v| v.id.as_str()))
+            .chain(stubs.iter().map(|v| v.as_str()))
+            .collect();
+        let node = |table: &str, id: &str| {
+            if added.contains(id) {
+                Ok(())
+            } else {
+                self.require_node(table, id)
+            }
+        };
+
+        let objects: HashSet<&str> = batch.objects.iter().map(|v| v.id.as_str()).collect();
+        let object = |table: &str, id: &str| {
+            if objects.contains(id) || self.object.contains_key(id) {
+                Ok(())
+            } else {
+                Err(anyhow!("insert or update on table \"{}\" violates foreign key constraint: object {} does not exist", table, id))
+            }
+        };
+
+        for peer in &batch.peers {
+            node("peer", &peer.id_right)?;
+            node("peer_observation", &peer.id_right)?;
+        }
+        for pin in &batch.pins {
+            object("node_object_pin", &pin.id_object)?;
+            object("pin_observation", &pin.id_object)?;
+        }
+        for node_addr in &batch.node_addrs {
+            node("node_addr", &node_addr.id_node)?;
+        }
+        for gateway in &batch.gateways {
+            node("node_gateway", &gateway.id_node)?;
+        }
+        for identity in &batch.identities {
+            node("node_identity", &identity.id_node)?;
+        }
+        for mismatch in &batch.id_mismatches {
+            node("node_id_mismatch", &mismatch.id_advertised)?;
+            node("node_id_mismatch", &mismatch.id_reported)?;
+        }
+
+        Ok(())
+    }
+
+    fn require_node(&self, table: &str, id: &str) -> anyhow::Result<()> {
+        if self.node.contains_key(id) {
+            Ok(())
+        } else {
+            Err(anyhow!("insert or update on table \"{}\" violates foreign key constraint: node {} does not exist", table, id))
+        }
+    }
+}
+
+impl MemoryStore {
+    // The active pins whose (id_node, id_object) key matches
+    fn active_pins(&self, f: impl Fn(&(String, String)) -> bool) -> Vec<NodePin> {
+        self.tables.lock().unwrap().node_object_pin.iter()
+            .filter(|(key, (_, _, _, active))| *active && f(key))
+            .map(|((id_node, id_object), (pin_type, seen_first, seen_last, _))| NodePin {
+                id_node: id_node.clone(),
+                id_object: id_object.clone(),
+                pin_type: Some(pin_type.clone()),
+                seen_first: *seen_first,
+                seen_last: *seen_last,
+            })
+            .collect()
+    }
+}
+
+// The keys in only one of the two sets, those in `to` first, each in order
+fn diff_rows(from: BTreeSet<(String, Option<String>)>, to: BTreeSet<(String, Option<String>)>) -> Vec<RunDiffRow> {
+    let appeared = to.difference(&from).map(|v| (true, v));
+    let vanished = from.difference(&to).map(|v| (false, v));
+
+    appeared.chain(vanished)
+        .map(|(appeared, (id, id_other))| RunDiffRow {
+            appeared,
+            id: id.clone(),
+            id_other: id_other.clone(),
+        })
+        .collect()
+}
+
+fn page<T>(rows: Vec<T>, limit: i64, offset: i64) -> Vec<T> {
+    rows.into_iter()
+        .skip(offset.max(0) as usize)
+        .take(limit.max(0) as usize)
+        .collect()
+}
+
+fn cutoff(age: Duration) -> anyhow::Result<DateTime<Utc>> {
+    Ok(Utc::now() - chrono::Duration::from_std(age)?)
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn migrate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_node(&self, id: &str) -> anyhow::Result<Option<Node>> {
+        Ok(self.tables.lock().unwrap().node.get(id).cloned())
+    }
+
+    async fn get_nodes(&self) -> anyhow::Result<Vec<Node>> {
+        let mut r: Vec<Node> = self.tables.lock().unwrap().node.values().cloned().collect();
+        r.sort_by_key(|v| v.seen_first);
+
+        Ok(r)
+    }
+
+    async fn add_node(&self, node: &Node) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+
+        match t.node.get_mut(&node.id) {
+            Some(v) => {
+                v.seen_last = node.seen_last;
+                v.public_addr = node.public_addr.clone();
+                v.api_candidate = node.api_candidate.clone();
+            }
+            None => {
+                t.node.insert(node.id.clone(), Node {
+                    scan_last: None,
+                    ..node.clone()
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn update_node(&self, update: &NodeUpdate) -> anyhow::Result<()> {
+        if let Some(v) = self.tables.lock().unwrap().node.get_mut(&update.id) {
+            v.seen_last = update.seen_last;
+            v.scan_last = update.scan_last;
+            v.public_addr = update.public_addr.clone();
+            v.api_candidate = update.api_candidate.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn set_node_scanned(&self, id: &str, scan_last: DateTime<Utc>) -> anyhow::Result<()> {
+        if let Some(v) = self.tables.lock().unwrap().node.get_mut(id) {
+            v.scan_last = Some(scan_last);
+        }
+
+        Ok(())
+    }
+
+    async fn get_unscanned_nodes(&self, min_time_since_last: Duration) -> anyhow::Result<Vec<Node>> {
+        let cutoff = cutoff(min_time_since_last)?;
+
+        Ok(self.tables.lock().unwrap().node.values()
+            .filter(|v| matches!(v.scan_last, Some(scan_last) if scan_last < cutoff))
+            .cloned()
+            .collect())
+    }
+
+    async fn deactivate_node_addrs(&self, id_node: &str) -> anyhow::Result<()> {
+        for ((id, _), (_, active)) in self.tables.lock().unwrap().node_addr.iter_mut() {
+            if id == id_node {
+                *active = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_node_addr(&self, node_addr: &NodeAddr) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+        t.require_node("node_addr", &node_addr.id_node)?;
+
+        t.node_addr.insert((node_addr.id_node.clone(), node_addr.addr.clone()), (node_addr.clone(), true));
+
+        Ok(())
+    }
+
+    async fn deactivate_node_peers(&self, id_node: &str) -> anyhow::Result<()> {
+        for ((id_left, _), (_, _, _, active)) in self.tables.lock().unwrap().peer.iter_mut() {
+            if id_left == id_node {
+                *active = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_peer(&self, peer: &Peer) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+        t.require_node("peer", &peer.id_left)?;
+        t.require_node("peer", &peer.id_right)?;
+
+        let now = Utc::now();
+        let row = t.peer.entry((peer.id_left.clone(), peer.id_right.clone()))
+            .or_insert_with(|| (peer.clone(), now, now, true));
+        row.0 = peer.clone();
+        row.2 = now;
+        row.3 = true;
+
+        Ok(())
+    }
+
+    async fn add_object(&self, object: &Object) -> anyhow::Result<()> {
+        self.tables.lock().unwrap().insert_object(object);
+
+        Ok(())
+    }
+
+    async fn add_node_object_pin(&self, node_object_pin: &NodeObjectPin) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+        t.require_node("node_object_pin", &node_object_pin.id_node)?;
+        if !t.object.contains_key(&node_object_pin.id_object) {
+            return Err(anyhow!("insert or update on table \"node_object_pin\" violates foreign key constraint: object {} does not exist",
+                               node_object_pin.id_object));
+        }
+
+        let now = Utc::now();
+        let (pin_type, _, seen_last, active) = t.node_object_pin
+            .entry((node_object_pin.id_node.clone(), node_object_pin.id_object.clone()))
+            .or_insert_with(|| (String::new(), now, now, true));
+        *pin_type = node_object_pin.pin_type.clone();
+        *seen_last = now;
+        *active = true;
+
+        Ok(())
+    }
+
+    async fn write_scan(&self, batch: &ScanBatch) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+
+        // Check foreign keys before changing anything, so that a failed batch leaves nothing behind like a rolled
+        // back transaction would. The nodes and objects the batch adds, stubs included, count as existing.
+        t.check_scan(batch)?;
+
+        let node = t.node.entry(batch.id_node.clone()).or_insert_with(|| Node {
+            id: batch.id_node.clone(),
+            seen_first: batch.scan_time,
+            seen_last: batch.scan_time,
+            scan_last: None,
+            public_addr: None,
+            api_candidate: None,
+        });
+        node.seen_last = batch.scan_time;
+        node.scan_last = Some(batch.scan_time);
+        t.node_scan.insert((batch.id_run, batch.id_node.clone()), (batch.scan_time, batch.api_addr.clone()));
+
+        for node in &batch.nodes {
+            match t.node.get_mut(&node.id) {
+                Some(v) => {
+                    v.seen_last = node.seen_last;
+                    v.public_addr = node.public_addr.clone();
+                    v.api_candidate = node.api_candidate.clone();
+                }
+                None => {
+                    t.node.insert(node.id.clone(), Node {
+                        scan_last: None,
+                        ..node.clone()
+                    });
+                }
+            }
+        }
+
+        for update in &batch.node_updates {
+            if let Some(v) = t.node.get_mut(&update.id) {
+                v.seen_last = update.seen_last;
+                v.scan_last = update.scan_last;
+                v.public_addr = update.public_addr.clone();
+                v.api_candidate = update.api_candidate.clone();
+            }
+        }
+
+        for id in batch.referenced_nodes() {
+            t.node.entry(id.clone()).or_insert_with(|| Node {
+                id,
+                seen_first: batch.scan_time,
+                seen_last: batch.scan_time,
+                scan_last: None,
+                public_addr: None,
+                api_candidate: None,
+            });
+        }
+
+        for object in &batch.objects {
+            t.insert_object(object);
+        }
+
+        for ((id_node, _), (pin_type, _, _, active)) in t.node_object_pin.iter_mut() {
+            if *id_node == batch.id_node && (*pin_type != "indirect" || batch.pins_indirect) {
+                *active = false;
+            }
+        }
+        // Pins whose object couldn't be stat'd are kept if the object is stored
+        let stored: Vec<NodeObjectPin> = batch.pins_without_stat.iter()
+            .filter(|v| t.object.contains_key(&v.id_object))
+            .cloned()
+            .collect();
+        for pin in batch.pins.iter().chain(&stored) {
+            let (pin_type, _, seen_last, active) = t.node_object_pin
+                .entry((batch.id_node.clone(), pin.id_object.clone()))
+                .or_insert_with(|| (String::new(), batch.scan_time, batch.scan_time, true));
+            *pin_type = pin.pin_type.clone();
+            *seen_last = batch.scan_time;
+            *active = true;
+
+            t.pin_observation.insert((batch.id_run, batch.id_node.clone(), pin.id_object.clone()), pin.pin_type.clone());
+        }
+
+        for link in &batch.links {
+            t.object_link.insert((link.id_parent.clone(), link.id_child.clone(), link.name.clone()));
+        }
+
+        for ((id_left, _), (_, _, _, active)) in t.peer.iter_mut() {
+            if *id_left == batch.id_node {
+                *active = false;
+            }
+        }
+        for peer in &batch.peers {
+            let row = t.peer.entry((batch.id_node.clone(), peer.id_right.clone()))
+                .or_insert_with(|| (peer.clone(), batch.scan_time, batch.scan_time, true));
+            row.0 = peer.clone();
+            row.2 = batch.scan_time;
+            row.3 = true;
+
+            t.peer_observation.push((peer.clone(), batch.scan_time, batch.id_run));
+        }
+
+        for node_addr in &batch.node_addrs {
+            t.node_addr.insert((node_addr.id_node.clone(), node_addr.addr.clone()), (node_addr.clone(), true));
+        }
+
+        for ((id_node, _), (_, check_last, active)) in t.node_gateway.iter_mut() {
+            if batch.gateways_checked.contains(id_node) {
+                *check_last = batch.scan_time;
+                *active = false;
+            }
+        }
+        for gateway in &batch.gateways {
+            t.node_gateway.insert((gateway.id_node.clone(), gateway.url.clone()), (gateway.clone(), batch.scan_time, true));
+        }
+
+        for mismatch in &batch.id_mismatches {
+            let key = (mismatch.id_advertised.clone(), mismatch.id_reported.clone(), mismatch.api_addr.clone());
+            let row = t.node_id_mismatch.entry(key).or_insert_with(|| (mismatch.clone(), batch.scan_time, batch.scan_time));
+            row.0.addr = mismatch.addr.clone();
+            row.2 = batch.scan_time;
+        }
+
+        for identity in &batch.identities {
+            let latest = t.node_identity.iter().rposition(|(v, _, _)| v.id_node == identity.id_node);
+
+            match latest {
+                Some(i) if t.node_identity[i].0.same_identity(identity) => {
+                    let (v, _, seen_last) = &mut t.node_identity[i];
+                    v.addresses = identity.addresses.clone();
+                    *seen_last = batch.scan_time;
+                }
+                _ => t.node_identity.push((identity.clone(), batch.scan_time, batch.scan_time)),
+            }
+        }
+
+        for v in &batch.frontier {
+            t.add_crawl_seen(&v.addr, &v.id_node);
+        }
+        for v in &batch.frontier {
+            if let Some(api_addr) = &v.api_addr {
+                t.add_crawl_job(&v.addr, &v.id_node, api_addr);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_scan_error(&self, error: &ScanError) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+        if !t.crawl_run.iter().any(|v| v.id == error.id_run) {
+            return Err(anyhow!("insert or update on table \"scan_error\" violates foreign key constraint: crawl run {} does not exist",
+                               error.id_run));
+        }
+
+        t.scan_error.push(error.clone());
+
+        Ok(())
+    }
+
+    async fn get_stats(&self) -> anyhow::Result<Stats> {
+        let t = self.tables.lock().unwrap();
+
+        Ok(Stats {
+            nodes: t.node.len() as i64,
+            nodes_public: t.node.values().filter(|v| v.public_addr.is_some()).count() as i64,
+            nodes_scanned: t.node.values().filter(|v| v.scan_last.is_some()).count() as i64,
+            peers_active: t.peer.values().filter(|v| v.3).count() as i64,
+            objects: t.object.len() as i64,
+            pins: t.node_object_pin.values().filter(|v| v.3).count() as i64,
+            objects_bytes: t.object.values().map(|v| v.cumulative_size).sum(),
+            pins_bytes: t.node_object_pin.iter()
+                .filter(|(_, v)| v.3)
+                .filter_map(|((_, id_object), _)| t.object.get(id_object))
+                .map(|v| v.cumulative_size)
+                .sum(),
+            queue_pending: t.crawl_queue.values().filter(|v| v.state == "pending" || v.state == "claimed").count() as i64,
+        })
+    }
+
+    async fn get_agent_versions(&self) -> anyhow::Result<Vec<AgentVersionCount>> {
+        let t = self.tables.lock().unwrap();
+
+        // Later rows are newer, so the last one seen for each node wins.
+        let mut latest: HashMap<&str, &str> = HashMap::new();
+        for (v, _, _) in &t.node_identity {
+            latest.insert(&v.id_node, &v.agent_version);
+        }
+
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for agent_version in latest.values() {
+            *counts.entry(*agent_version).or_insert(0) += 1;
+        }
+
+        let mut r: Vec<AgentVersionCount> = counts.into_iter()
+            .map(|(agent_version, nodes)| AgentVersionCount { agent_version: agent_version.to_owned(), nodes })
+            .collect();
+        r.sort_by(|a, b| b.nodes.cmp(&a.nodes).then_with(|| a.agent_version.cmp(&b.agent_version)));
+
+        Ok(r)
+    }
+
+    async fn get_roots_containing(&self, id_object: &str) -> anyhow::Result<Vec<RootPins>> {
+        let t = self.tables.lock().unwrap();
+
+        // Walk up from the object, collecting it and every ancestor once.
+        let mut ancestors = HashSet::new();
+        let mut pending = vec![id_object.to_owned()];
+        while let Some(id) = pending.pop() {
+            if ancestors.insert(id.clone()) {
+                pending.extend(t.object_link.iter()
+                    .filter(|(_, id_child, _)| *id_child == id)
+                    .map(|(id_parent, _, _)| id_parent.clone()));
+            }
+        }
+
+        let mut nodes: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for ((id_node, id_object), (_, _, _, active)) in &t.node_object_pin {
+            if *active && ancestors.contains(id_object) {
+                nodes.entry(id_object.as_str()).or_default().insert(id_node.as_str());
+            }
+        }
+
+        let mut roots: Vec<RootPins> = nodes.into_iter()
+            .map(|(id_object, nodes)| RootPins { id_object: id_object.to_owned(), nodes: nodes.len() as i64 })
+            .collect();
+        roots.sort_by(|a, b| b.nodes.cmp(&a.nodes).then_with(|| a.id_object.cmp(&b.id_object)));
+
+        Ok(roots)
+    }
+
+    async fn get_object(&self, id: &str) -> anyhow::Result<Option<Object>> {
+        Ok(self.tables.lock().unwrap().object.get(id).cloned())
+    }
+
+    async fn get_public_nodes(&self, limit: i64, offset: i64) -> anyhow::Result<Vec<Node>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<Node> = t.node.values().filter(|v| v.public_addr.is_some()).cloned().collect();
+        r.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(page(r, limit, offset))
+    }
+
+    async fn get_node_peers(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<PeerEdge>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<PeerEdge> = t.peer.iter()
+            .filter(|((id_left, _), (_, _, _, active))| id_left == id_node && *active)
+            .map(|((id_left, id_right), (peer, seen_first, seen_last, _))| PeerEdge {
+                id_left: id_left.clone(),
+                id_right: id_right.clone(),
+                addr: Some(peer.addr.clone()),
+                latency_ms: peer.latency_ms,
+                direction: peer.direction.clone(),
+                muxer: peer.muxer.clone(),
+                seen_first: *seen_first,
+                seen_last: *seen_last,
+            })
+            .collect();
+        r.sort_by(|a, b| a.id_right.cmp(&b.id_right));
+
+        Ok(page(r, limit, offset))
+    }
+
+    async fn get_node_pins(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        let mut r = self.active_pins(|(id, _)| id == id_node);
+        r.sort_by(|a, b| a.id_object.cmp(&b.id_object));
+
+        Ok(page(r, limit, offset))
+    }
+
+    async fn get_providers(&self, id_object: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        let mut r = self.active_pins(|(_, id)| id == id_object);
+        r.sort_by(|a, b| a.id_node.cmp(&b.id_node));
+
+        Ok(page(r, limit, offset))
+    }
+
+    async fn get_graph_nodes(&self, at: Option<DateTime<Utc>>) -> anyhow::Result<Vec<GraphNode>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut pins: HashMap<&str, i64> = HashMap::new();
+        match at {
+            None => {
+                for ((id_node, _), (_, _, _, active)) in &t.node_object_pin {
+                    if *active {
+                        *pins.entry(id_node).or_default() += 1;
+                    }
+                }
+            }
+            Some(at) => {
+                // Each node's last scan at or before the time, and the pins it listed
+                let mut last_scan: HashMap<&str, (DateTime<Utc>, i64)> = HashMap::new();
+                for ((id_run, id_node), (scanned, _)) in t.node_scan.iter().filter(|(_, (scanned, _))| *scanned <= at) {
+                    let v = last_scan.entry(id_node).or_insert((*scanned, *id_run));
+                    *v = (*v).max((*scanned, *id_run));
+                }
+
+                for (id_run, id_node, _) in t.pin_observation.keys() {
+                    if last_scan.get(id_node.as_str()).map(|(_, v)| v) == Some(id_run) {
+                        *pins.entry(id_node).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        let mut r: Vec<GraphNode> = t.node.values()
+            .filter(|v| at.map_or(true, |at| v.seen_first <= at))
+            .map(|v| GraphNode {
+                id: v.id.clone(),
+                public_addr: v.public_addr.clone(),
+                seen_first: v.seen_first,
+                seen_last: v.seen_last,
+                pins: pins.get(v.id.as_str()).copied().unwrap_or_default(),
+            })
+            .collect();
+        r.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(r)
+    }
+
+    async fn get_graph_edges(&self, at: Option<DateTime<Utc>>, active: Option<bool>) -> anyhow::Result<Vec<GraphEdge>> {
+        let t = self.tables.lock().unwrap();
+
+        // (id_left, id_right) to whether the edge was active and its latency, if any
+        let edges: HashMap<(String, String), (bool, Option<i32>)> = match at {
+            None => t.peer.iter()
+                .map(|(key, (peer, _, _, is_active))| (key.clone(), (*is_active, peer.latency_ms)))
+                .collect(),
+            Some(at) => {
+                // id_left's last scan at or before the time, whether or not it saw any peers
+                let mut last_scan: HashMap<&str, DateTime<Utc>> = HashMap::new();
+                let observed = t.peer_observation.iter().map(|(peer, observed, _)| (peer.id_left.as_str(), *observed));
+                let scanned = t.node_scan.iter().map(|((_, id_node), (scanned, _))| (id_node.as_str(), *scanned));
+                for (id_left, time) in observed.chain(scanned).filter(|(_, time)| *time <= at) {
+                    let v = last_scan.entry(id_left).or_insert(time);
+                    *v = (*v).max(time);
+                }
+
+                // The edges id_left had by then, active if that scan saw them
+                let mut r: HashMap<(String, String), (bool, Option<i32>)> = t.peer.iter()
+                    .filter(|((id_left, _), (_, seen_first, _, _))| *seen_first <= at && last_scan.contains_key(id_left.as_str()))
+                    .map(|(key, _)| (key.clone(), (false, None)))
+                    .collect();
+                for (peer, observed, _) in &t.peer_observation {
+                    if last_scan.get(peer.id_left.as_str()) != Some(observed) {
+                        continue;
+                    }
+                    if let Some((is_active, latency_ms)) = r.get_mut(&(peer.id_left.clone(), peer.id_right.clone())) {
+                        *latency_ms = match (*is_active, *latency_ms, peer.latency_ms) {
+                            (true, Some(a), Some(b)) => Some(a.min(b)),
+                            (true, a, b) => a.or(b),
+                            (false, _, b) => b,
+                        };
+                        *is_active = true;
+                    }
+                }
+                r
+            }
+        };
+
+        let mut r: Vec<GraphEdge> = edges.into_iter()
+            .filter_map(|(key, (is_active, latency_ms))| {
+                let (_, seen_first, seen_last, _) = t.peer.get(&key)?;
+                if active.map_or(false, |v| v != is_active) {
+                    return None;
+                }
+
+                Some(GraphEdge {
+                    id_left: key.0,
+                    id_right: key.1,
+                    active: is_active,
+                    seen_first: *seen_first,
+                    seen_last: *seen_last,
+                    latency_ms,
+                })
+            })
+            .collect();
+        r.sort_by(|a, b| (&a.id_left, &a.id_right).cmp(&(&b.id_left, &b.id_right)));
+
+        Ok(r)
+    }
+
+    async fn export_nodes(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Node>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<Node> = t.node.values()
+            .filter(|v| v.id.as_str() > after && since.map_or(true, |since| v.seen_last >= since))
+            .cloned()
+            .collect();
+        r.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(page(r, limit, 0))
+    }
+
+    async fn export_node_addrs(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeAddrRow>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<NodeAddrRow> = t.node_addr.iter()
+            .filter(|((id_node, addr), _)| (id_node.as_str(), addr.as_str()) > after)
+            .filter(|((id_node, _), _)| match (since, t.node.get(id_node)) {
+                (Some(since), Some(node)) => node.seen_last >= since,
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .map(|(_, (v, active))| NodeAddrRow {
+                id_node: v.id_node.clone(),
+                addr: v.addr.clone(),
+                transport: v.transport.clone(),
+                host: v.host.clone(),
+                port: v.port,
+                relay: v.relay,
+                active: *active,
+            })
+            .collect();
+        r.sort_by(|a, b| (&a.id_node, &a.addr).cmp(&(&b.id_node, &b.addr)));
+
+        Ok(page(r, limit, 0))
+    }
+
+    async fn export_peers(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<PeerRow>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<PeerRow> = t.peer.iter()
+            .filter(|((id_left, id_right), _)| (id_left.as_str(), id_right.as_str()) > after)
+            .filter(|(_, (_, _, seen_last, _))| since.map_or(true, |since| *seen_last >= since))
+            .map(|((id_left, id_right), (peer, seen_first, seen_last, active))| PeerRow {
+                id_left: id_left.clone(),
+                id_right: id_right.clone(),
+                active: *active,
+                seen_first: *seen_first,
+                seen_last: *seen_last,
+                addr: Some(peer.addr.clone()),
+                latency_ms: peer.latency_ms,
+                direction: peer.direction.clone(),
+                muxer: peer.muxer.clone(),
+            })
+            .collect();
+        r.sort_by(|a, b| (&a.id_left, &a.id_right).cmp(&(&b.id_left, &b.id_right)));
+
+        Ok(page(r, limit, 0))
+    }
+
+    async fn export_objects(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Object>> {
+        let t = self.tables.lock().unwrap();
+
+        let pinned: HashSet<&str> = match since {
+            Some(since) => t.node_object_pin.iter()
+                .filter(|(_, (_, _, seen_last, _))| *seen_last >= since)
+                .map(|((_, id_object), _)| id_object.as_str())
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        let mut r: Vec<Object> = t.object.values()
+            .filter(|v| v.id.as_str() > after && (since.is_none() || pinned.contains(v.id.as_str())))
+            .cloned()
+            .collect();
+        r.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(page(r, limit, 0))
+    }
+
+    async fn export_node_object_pins(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeObjectPinRow>> {
+        let t = self.tables.lock().unwrap();
+
+        let mut r: Vec<NodeObjectPinRow> = t.node_object_pin.iter()
+            .filter(|((id_node, id_object), _)| (id_node.as_str(), id_object.as_str()) > after)
+            .filter(|(_, (_, _, seen_last, _))| since.map_or(true, |since| *seen_last >= since))
+            .map(|((id_node, id_object), (pin_type, seen_first, seen_last, active))| NodeObjectPinRow {
+                id_node: id_node.clone(),
+                id_object: id_object.clone(),
+                pin_type: Some(pin_type.clone()),
+                seen_first: *seen_first,
+                seen_last: *seen_last,
+                active: *active,
+            })
+            .collect();
+        r.sort_by(|a, b| (&a.id_node, &a.id_object).cmp(&(&b.id_node, &b.id_object)));
+
+        Ok(page(r, limit, 0))
+    }
+
+    async fn start_crawl_run(&self, kind: &str) -> anyhow::Result<i64> {
+        let mut t = self.tables.lock().unwrap();
+        let id = t.crawl_run.len() as i64 + 1;
+        t.crawl_run.push(CrawlRun {
+            id,
+            kind: kind.to_owned(),
+            started: Utc::now(),
+            finished: None,
+        });
+
+        Ok(id)
+    }
+
+    async fn finish_crawl_run(&self, id: i64) -> anyhow::Result<()> {
+        let mut t = self.tables.lock().unwrap();
+        if let Some(v) = t.crawl_run.iter_mut().find(|v| v.id == id) {
+            v.finished = Some(Utc::now());
+        }
+
+        Ok(())
+    }
+
+    async fn get_crawl_runs(&self) -> anyhow::Result<Vec<CrawlRun>> {
+        Ok(self.tables.lock().unwrap().crawl_run.iter().rev().cloned().collect())
+    }
+
+    async fn diff_run_nodes(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let t = self.tables.lock().unwrap();
+        let nodes = |id_run: i64| -> BTreeSet<(String, Option<String>)> {
+            t.node_scan.keys()
+                .filter(|(v, _)| *v == id_run)
+                .map(|(_, id_node)| id_node.clone())
+                .chain(t.peer_observation.iter()
+                    .filter(|(_, _, v)| *v == id_run)
+                    .flat_map(|(peer, _, _)| vec![peer.id_left.clone(), peer.id_right.clone()]))
+                .map(|v| (v, None))
+                .collect()
+        };
+
+        Ok(diff_rows(nodes(from), nodes(to)))
+    }
+
+    async fn diff_run_edges(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let t = self.tables.lock().unwrap();
+        let edges = |id_run: i64| -> BTreeSet<(String, Option<String>)> {
+            t.peer_observation.iter()
+                .filter(|(_, _, v)| *v == id_run)
+                .map(|(peer, _, _)| (peer.id_left.clone(), Some(peer.id_right.clone())))
+                .collect()
+        };
+
+        Ok(diff_rows(edges(from), edges(to)))
+    }
+
+    async fn diff_run_apis(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let t = self.tables.lock().unwrap();
+        let apis = |id_run: i64| -> BTreeSet<(String, Option<String>)> {
+            t.node_scan.iter()
+                .filter(|((v, _), (_, api_addr))| *v == id_run && api_addr.is_some())
+                .map(|((_, id_node), (_, api_addr))| (id_node.clone(), api_addr.clone()))
+                .collect()
+        };
+
+        Ok(diff_rows(apis(from), apis(to)))
+    }
+
+    async fn diff_run_pins(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let t = self.tables.lock().unwrap();
+        let pins = |id_run: i64| -> BTreeSet<(String, Option<String>)> {
+            t.pin_observation.keys()
+                .filter(|(v, _, _)| *v == id_run)
+                .map(|(_, id_node, id_object)| (id_node.clone(), Some(id_object.clone())))
+                .collect()
+        };
+
+        Ok(diff_rows(pins(from), pins(to)))
+    }
+
+    async fn get_unseen_addrs(&self, addrs: &[String]) -> anyhow::Result<Vec<String>> {
+        let t = self.tables.lock().unwrap();
+
+        Ok(addrs.iter().filter(|v| !t.crawl_queue.contains_key(*v)).cloned().collect())
+    }
+
+    async fn add_crawl_job(&self, addr: &str, id_node: &str, api_addr: &str) -> anyhow::Result<()> {
+        self.tables.lock().unwrap().add_crawl_job(addr, id_node, api_addr);
+
+        Ok(())
+    }
+
+    async fn claim_crawl_jobs(&self, limit: i64) -> anyhow::Result<Vec<CrawlJob>> {
+        let mut t = self.tables.lock().unwrap();
+
+        let mut pending: Vec<(&String, &mut QueueRow)> = t.crawl_queue.iter_mut()
+            .filter(|(_, v)| v.state == "pending")
+            .collect();
+        pending.sort_by_key(|(_, v)| v.enqueued);
+
+        let now = Utc::now();
+        Ok(pending.into_iter()
+            .take(limit.max(0) as usize)
+            .map(|(addr, v)| {
+                v.state = "claimed";
+                v.attempts += 1;
+                v.claimed = Some(now);
+
+                CrawlJob {
+                    addr: addr.clone(),
+                    id_node: v.id_node.clone(),
+                    api_addr: v.api_addr.clone().unwrap_or_default(),
+                    attempts: v.attempts,
+                }
+            })
+            .collect())
+    }
+
+    async fn complete_crawl_job(&self, addr: &str) -> anyhow::Result<()> {
+        if let Some(v) = self.tables.lock().unwrap().crawl_queue.get_mut(addr) {
+            v.state = "done";
+            v.claimed = None;
+        }
+
+        Ok(())
+    }
+
+    async fn fail_crawl_job(&self, addr: &str, max_attempts: i32) -> anyhow::Result<()> {
+        if let Some(v) = self.tables.lock().unwrap().crawl_queue.get_mut(addr) {
+            v.state = if v.attempts >= max_attempts { "failed" } else { "pending" };
+            v.claimed = None;
+        }
+
+        Ok(())
+    }
+
+    async fn release_crawl_jobs(&self, addrs: &[String]) -> anyhow::Result<u64> {
+        let mut t = self.tables.lock().unwrap();
+        let mut released = 0;
+
+        for addr in addrs {
+            if let Some(v) = t.crawl_queue.get_mut(addr) {
+                if v.state == "claimed" {
+                    v.state = "pending";
+                    v.claimed = None;
+                    released += 1;
+                }
+            }
+        }
+
+        Ok(released)
+    }
+
+    async fn count_open_crawl_jobs(&self) -> anyhow::Result<i64> {
+        Ok(self.tables.lock().unwrap().crawl_queue.values()
+            .filter(|v| v.state == "pending" || v.state == "claimed")
+            .count() as i64)
+    }
+
+    async fn release_stale_crawl_jobs(&self, claim_timeout: Duration) -> anyhow::Result<u64> {
+        let cutoff = cutoff(claim_timeout)?;
+        let mut released = 0;
+
+        for v in self.tables.lock().unwrap().crawl_queue.values_mut() {
+            if v.state == "claimed" && matches!(v.claimed, Some(claimed) if claimed < cutoff) {
+                v.state = "pending";
+                v.claimed = None;
+                released += 1;
+            }
+        }
+
+        Ok(released)
+    }
+
+    async fn clear_crawl_queue(&self) -> anyhow::Result<()> {
+        self.tables.lock().unwrap().crawl_queue.clear();
+
+        Ok(())
+    }
+
+    async fn close(&self) {}
+}
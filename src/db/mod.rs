@@ -1,2 +1,6 @@
+pub mod memory;
 pub mod model;
+pub mod postgres;
 pub mod schema;
+pub mod sqlite;
+pub mod store;
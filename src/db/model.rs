@@ -1,17 +1,107 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
 use sqlx::{Pool, Postgres, query};
 use sqlx::postgres::types::PgInterval;
 
-use crate::db::schema::{Node, NodeAddr, NodeObjectPin, NodeUpdate, Object, Peer};
+use crate::cid::Cid;
+use crate::db::schema::{AgentVersionCount, CrawlJob, CrawlRun, GraphEdge, GraphNode, Node, NodeAddr, NodeAddrRow, NodeGateway, NodeIdMismatch, NodeIdentity, NodeObjectPin, NodeObjectPinRow, NodePin, NodeUpdate, Object, Peer, PeerEdge, PeerRow, RootPins, RunDiffRow, ScanBatch, ScanError, Stats};
+
+// The data half of migration 0016: rewrites objects from before 0011 under their canonical CID, merging their pins
+// and links into the object's row if it has since been seen under that ID. Objects whose ID isn't a CID this can
+// parse are left alone. Each object is done in its own transaction, so an interrupted run just continues next time.
+pub async fn normalize_object_ids(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<()> {
+    let rows = query!("SELECT id FROM object WHERE cid_version IS NULL")
+        .fetch_all(conn)
+        .await?;
+
+    for r in rows {
+        let cid: Cid = match r.id.parse() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let id = cid.to_string();
+
+        let mut tx = conn.begin().await?;
+
+        if id != r.id {
+            query!("INSERT INTO object (id, size, cumulative_size, num_links, block_size, links_size, file_type, file_size,
+                        file_blocks)
+                    SELECT $2, size, cumulative_size, num_links, block_size, links_size, file_type, file_size, file_blocks
+                    FROM object WHERE id=$1
+                    ON CONFLICT ON CONSTRAINT object_pk DO NOTHING",
+                r.id, id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                    SELECT id_node, $2, pin_type, seen_first, seen_last, active FROM node_object_pin WHERE id_object=$1
+                    ON CONFLICT ON CONSTRAINT node_object_pin_pk DO UPDATE
+                        SET pin_type=COALESCE(node_object_pin.pin_type, excluded.pin_type),
+                            seen_first=LEAST(node_object_pin.seen_first, excluded.seen_first),
+                            seen_last=GREATEST(node_object_pin.seen_last, excluded.seen_last),
+                            active=node_object_pin.active OR excluded.active",
+                r.id, id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("INSERT INTO object_link (id_parent, id_child, name)
+                    SELECT CASE WHEN id_parent=$1 THEN $2 ELSE id_parent END, CASE WHEN id_child=$1 THEN $2 ELSE id_child END, name
+                    FROM object_link WHERE id_parent=$1 OR id_child=$1
+                    ON CONFLICT ON CONSTRAINT object_link_pk DO NOTHING",
+                r.id, id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("DELETE FROM object_link WHERE id_parent=$1 OR id_child=$1",
+                r.id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                    SELECT id_run, id_node, $2, pin_type FROM pin_observation WHERE id_object=$1
+                    ON CONFLICT ON CONSTRAINT pin_observation_pk DO NOTHING",
+                r.id, id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("DELETE FROM pin_observation WHERE id_object=$1",
+                r.id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("DELETE FROM node_object_pin WHERE id_object=$1",
+                r.id)
+                .execute(&mut tx)
+                .await?;
+
+            query!("DELETE FROM object WHERE id=$1",
+                r.id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        query!("UPDATE object SET cid_version=$2, codec=$3, hash_function=$4 WHERE id=$1 AND cid_version IS NULL",
+            id, cid.version as i16, cid.codec_name(), cid.hash_function_name())
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
 
 pub async fn get_node(
     conn: &Pool<Postgres>,
     id: &str,
 ) -> anyhow::Result<Option<Node>> {
-    let r = query!("SELECT id, seen_first, seen_last, scan_last, public_addr FROM node WHERE id=$1",
+    let r = query!("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node WHERE id=$1",
         id)
         .fetch_optional(conn)
         .await?;
@@ -23,6 +113,7 @@ pub async fn get_node(
             seen_last: r.seen_last,
             scan_last: r.scan_last,
             public_addr: r.public_addr,
+            api_candidate: r.api_candidate,
         }),
         _ => None,
     })
@@ -32,10 +123,10 @@ pub async fn add_node(
     conn: &Pool<Postgres>,
     node: &Node,
 ) -> anyhow::Result<()> {
-    query!("INSERT INTO node (id, seen_first, seen_last, public_addr)
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT ON CONSTRAINT node_pk DO UPDATE SET seen_last=$3, public_addr=$4",
-        node.id, node.seen_first, node.seen_last, node.public_addr)
+    query!("INSERT INTO node (id, seen_first, seen_last, public_addr, api_candidate)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT ON CONSTRAINT node_pk DO UPDATE SET seen_last=$3, public_addr=$4, api_candidate=$5",
+        node.id, node.seen_first, node.seen_last, node.public_addr, node.api_candidate)
         .execute(conn)
         .await?;
 
@@ -47,9 +138,22 @@ pub async fn update_node(
     update: &NodeUpdate,
 ) -> anyhow::Result<()> {
     query!("UPDATE node
-            SET seen_last=$1, scan_last=$2, public_addr=$3
-            WHERE id=$4",
-        update.seen_last, update.scan_last, update.public_addr, update.id)
+            SET seen_last=$1, scan_last=$2, public_addr=$3, api_candidate=$4
+            WHERE id=$5",
+        update.seen_last, update.scan_last, update.public_addr, update.api_candidate, update.id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn set_node_scanned(
+    conn: &Pool<Postgres>,
+    id: &str,
+    scan_last: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    query!("UPDATE node SET scan_last=$1 WHERE id=$2",
+        scan_last, id)
         .execute(conn)
         .await?;
 
@@ -72,10 +176,10 @@ pub async fn add_node_addr(
     conn: &Pool<Postgres>,
     node_addr: &NodeAddr,
 ) -> anyhow::Result<()> {
-    query!("INSERT INTO node_addr (id_node, addr, active)
-            VALUES ($1, $2, TRUE)
-            ON CONFLICT ON CONSTRAINT node_addr_pk DO UPDATE SET active=TRUE",
-        node_addr.id_node, node_addr.addr)
+    query!("INSERT INTO node_addr (id_node, addr, transport, host, port, relay, active)
+            VALUES ($1, $2, $3, $4, $5, $6, TRUE)
+            ON CONFLICT ON CONSTRAINT node_addr_pk DO UPDATE SET transport=$3, host=$4, port=$5, relay=$6, active=TRUE",
+        node_addr.id_node, node_addr.addr, node_addr.transport, node_addr.host, node_addr.port, node_addr.relay)
         .execute(conn)
         .await?;
 
@@ -98,10 +202,11 @@ pub async fn add_peer(
     conn: &Pool<Postgres>,
     peer: &Peer,
 ) -> anyhow::Result<()> {
-    query!("INSERT INTO peer (id_left, id_right, active)
-            VALUES ($1, $2, TRUE)
-            ON CONFLICT ON CONSTRAINT peer_pk DO UPDATE SET active=TRUE",
-        peer.id_left, peer.id_right)
+    query!("INSERT INTO peer (id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last, active)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7, TRUE)
+            ON CONFLICT ON CONSTRAINT peer_pk DO UPDATE
+                SET addr=$3, latency_ms=$4, direction=$5, muxer=$6, seen_last=$7, active=TRUE",
+        peer.id_left, peer.id_right, peer.addr, peer.latency_ms, peer.direction, peer.muxer, Utc::now())
         .execute(conn)
         .await?;
 
@@ -112,10 +217,14 @@ pub async fn add_object(
     conn: &Pool<Postgres>,
     object: &Object,
 ) -> anyhow::Result<()> {
-    query!("INSERT INTO object (id, size)
-            VALUES ($1, $2)
-            ON CONFLICT ON CONSTRAINT object_pk DO NOTHING",
-        object.id, object.size)
+    query!("INSERT INTO object (id, cid_version, codec, hash_function, size, cumulative_size, num_links, block_size, links_size,
+                file_type, file_size, file_blocks)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            ON CONFLICT ON CONSTRAINT object_pk DO UPDATE
+                SET cid_version=GREATEST(object.cid_version, $2), codec=$3, hash_function=$4, size=$5, cumulative_size=$6, num_links=$7, block_size=$8,
+                    links_size=$9, file_type=$10, file_size=$11, file_blocks=$12",
+        object.id, object.cid_version, object.codec, object.hash_function, object.size, object.cumulative_size,
+        object.num_links, object.block_size, object.links_size, object.file_type, object.file_size, object.file_blocks)
         .execute(conn)
         .await?;
 
@@ -126,10 +235,23 @@ pub async fn add_node_object_pin(
     conn: &Pool<Postgres>,
     node_object_pin: &NodeObjectPin,
 ) -> anyhow::Result<()> {
-    query!("INSERT INTO node_object_pin (id_node, id_object)
-            VALUES ($1, $2)
-            ON CONFLICT ON CONSTRAINT node_object_pin_pk DO NOTHING",
-        node_object_pin.id_node, node_object_pin.id_object)
+    query!("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+            VALUES ($1, $2, $3, $4, $4, TRUE)
+            ON CONFLICT ON CONSTRAINT node_object_pin_pk DO UPDATE SET pin_type=$3, seen_last=$4, active=TRUE",
+        node_object_pin.id_node, node_object_pin.id_object, node_object_pin.pin_type, Utc::now())
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn add_scan_error(
+    conn: &Pool<Postgres>,
+    error: &ScanError,
+) -> anyhow::Result<()> {
+    query!("INSERT INTO scan_error (id_node, addr, kind, message, occurred, id_run)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        error.id_node, error.addr, error.kind, error.message, error.occurred, error.id_run)
         .execute(conn)
         .await?;
 
@@ -152,6 +274,7 @@ pub async fn get_unscanned_nodes(
                 seen_last: row.seen_last,
                 scan_last: row.scan_last,
                 public_addr: row.public_addr,
+                api_candidate: row.api_candidate,
             }
         })
         .fetch(conn);
@@ -163,3 +286,1087 @@ pub async fn get_unscanned_nodes(
 
     Ok(result)
 }
+
+pub async fn get_nodes(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<Vec<Node>> {
+    let mut stream = query!("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+            ORDER BY seen_first")
+        .map(|row| {
+            Node {
+                id: row.id,
+                seen_first: row.seen_first,
+                seen_last: row.seen_last,
+                scan_last: row.scan_last,
+                public_addr: row.public_addr,
+                api_candidate: row.api_candidate,
+            }
+        })
+        .fetch(conn);
+
+    let mut result = Vec::new();
+    while let Some(row) = stream.try_next().await? {
+        result.push(row);
+    }
+
+    Ok(result)
+}
+
+pub async fn get_stats(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<Stats> {
+    let r = query!(r#"SELECT
+                (SELECT COUNT(*) FROM node) AS "nodes!",
+                (SELECT COUNT(*) FROM node WHERE public_addr IS NOT NULL) AS "nodes_public!",
+                (SELECT COUNT(*) FROM node WHERE scan_last IS NOT NULL) AS "nodes_scanned!",
+                (SELECT COUNT(*) FROM peer WHERE active) AS "peers_active!",
+                (SELECT COUNT(*) FROM object) AS "objects!",
+                (SELECT COUNT(*) FROM node_object_pin WHERE active) AS "pins!",
+                (SELECT COALESCE(SUM(cumulative_size), 0)::BIGINT FROM object) AS "objects_bytes!",
+                (SELECT COALESCE(SUM(o.cumulative_size), 0)::BIGINT FROM node_object_pin AS p
+                    JOIN object AS o ON o.id=p.id_object WHERE p.active) AS "pins_bytes!",
+                (SELECT COUNT(*) FROM crawl_queue WHERE state IN ('pending', 'claimed')) AS "queue_pending!""#)
+        .fetch_one(conn)
+        .await?;
+
+    Ok(Stats {
+        nodes: r.nodes,
+        nodes_public: r.nodes_public,
+        nodes_scanned: r.nodes_scanned,
+        peers_active: r.peers_active,
+        objects: r.objects,
+        pins: r.pins,
+        objects_bytes: r.objects_bytes,
+        pins_bytes: r.pins_bytes,
+        queue_pending: r.queue_pending,
+    })
+}
+
+pub async fn get_agent_versions(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<Vec<AgentVersionCount>> {
+    let r = query!(r#"SELECT agent_version, COUNT(*) AS "nodes!" FROM (
+                SELECT DISTINCT ON (id_node) id_node, agent_version FROM node_identity
+                ORDER BY id_node, seen_last DESC
+            ) AS latest
+            GROUP BY agent_version
+            ORDER BY 2 DESC, 1"#)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|row| AgentVersionCount {
+            agent_version: row.agent_version,
+            nodes: row.nodes,
+        })
+        .collect())
+}
+
+pub async fn get_roots_containing(
+    conn: &Pool<Postgres>,
+    id_object: &str,
+) -> anyhow::Result<Vec<RootPins>> {
+    let r = query!(r#"WITH RECURSIVE ancestor (id) AS (
+                SELECT $1::VARCHAR
+                UNION
+                SELECT l.id_parent FROM object_link AS l JOIN ancestor AS a ON l.id_child=a.id
+            )
+            SELECT p.id_object AS "id_object!", COUNT(DISTINCT p.id_node) AS "nodes!"
+            FROM ancestor AS a JOIN node_object_pin AS p ON p.id_object=a.id AND p.active
+            GROUP BY p.id_object
+            ORDER BY 2 DESC, 1"#,
+        id_object)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|row| RootPins {
+            id_object: row.id_object,
+            nodes: row.nodes,
+        })
+        .collect())
+}
+
+pub async fn get_object(
+    conn: &Pool<Postgres>,
+    id: &str,
+) -> anyhow::Result<Option<Object>> {
+    let r = query!(r#"SELECT id, cid_version, codec, hash_function, size,
+                COALESCE(cumulative_size, 0) AS "cumulative_size!", COALESCE(num_links, 0) AS "num_links!",
+                COALESCE(block_size, 0) AS "block_size!", COALESCE(links_size, 0) AS "links_size!",
+                file_type, file_size, file_blocks
+            FROM object WHERE id=$1"#,
+        id)
+        .fetch_optional(conn)
+        .await?;
+
+    Ok(match r {
+        Some(r) => Some(Object {
+            id: r.id,
+            cid_version: r.cid_version,
+            codec: r.codec,
+            hash_function: r.hash_function,
+            size: r.size,
+            cumulative_size: r.cumulative_size,
+            num_links: r.num_links,
+            block_size: r.block_size,
+            links_size: r.links_size,
+            file_type: r.file_type,
+            file_size: r.file_size,
+            file_blocks: r.file_blocks,
+        }),
+        _ => None,
+    })
+}
+
+pub async fn get_public_nodes(
+    conn: &Pool<Postgres>,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<Node>> {
+    let r = query!("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+            WHERE public_addr IS NOT NULL
+            ORDER BY id
+            LIMIT $1 OFFSET $2",
+        limit, offset)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| Node {
+            id: r.id,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            scan_last: r.scan_last,
+            public_addr: r.public_addr,
+            api_candidate: r.api_candidate,
+        })
+        .collect())
+}
+
+pub async fn get_node_peers(
+    conn: &Pool<Postgres>,
+    id_node: &str,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<PeerEdge>> {
+    let r = query!("SELECT id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last FROM peer
+            WHERE id_left=$1 AND active
+            ORDER BY id_right
+            LIMIT $2 OFFSET $3",
+        id_node, limit, offset)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| PeerEdge {
+            id_left: r.id_left,
+            id_right: r.id_right,
+            addr: r.addr,
+            latency_ms: r.latency_ms,
+            direction: r.direction,
+            muxer: r.muxer,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+        })
+        .collect())
+}
+
+pub async fn get_node_pins(
+    conn: &Pool<Postgres>,
+    id_node: &str,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<NodePin>> {
+    let r = query!("SELECT id_node, id_object, pin_type, seen_first, seen_last FROM node_object_pin
+            WHERE id_node=$1 AND active
+            ORDER BY id_object
+            LIMIT $2 OFFSET $3",
+        id_node, limit, offset)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| NodePin {
+            id_node: r.id_node,
+            id_object: r.id_object,
+            pin_type: r.pin_type,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+        })
+        .collect())
+}
+
+pub async fn get_providers(
+    conn: &Pool<Postgres>,
+    id_object: &str,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<NodePin>> {
+    let r = query!("SELECT id_node, id_object, pin_type, seen_first, seen_last FROM node_object_pin
+            WHERE id_object=$1 AND active
+            ORDER BY id_node
+            LIMIT $2 OFFSET $3",
+        id_object, limit, offset)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| NodePin {
+            id_node: r.id_node,
+            id_object: r.id_object,
+            pin_type: r.pin_type,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+        })
+        .collect())
+}
+
+pub async fn get_graph_nodes(
+    conn: &Pool<Postgres>,
+    at: Option<DateTime<Utc>>,
+) -> anyhow::Result<Vec<GraphNode>> {
+    let at = match at {
+        Some(v) => v,
+        None => {
+            let r = query!(r#"SELECT n.id, n.public_addr, n.seen_first, n.seen_last, COUNT(p.id_object) AS "pins!"
+                    FROM node AS n
+                    LEFT JOIN node_object_pin AS p ON p.id_node=n.id AND p.active
+                    GROUP BY n.id
+                    ORDER BY n.id"#)
+                .fetch_all(conn)
+                .await?;
+
+            return Ok(r.into_iter()
+                .map(|r| GraphNode {
+                    id: r.id,
+                    public_addr: r.public_addr,
+                    seen_first: r.seen_first,
+                    seen_last: r.seen_last,
+                    pins: r.pins,
+                })
+                .collect());
+        }
+    };
+
+    // The pins listed by the node's last scan at or before the time
+    let r = query!(r#"SELECT n.id, n.public_addr, n.seen_first, n.seen_last, COUNT(o.id_object) AS "pins!"
+            FROM node AS n
+            LEFT JOIN pin_observation AS o ON o.id_node=n.id
+                AND o.id_run=(SELECT s.id_run FROM node_scan AS s
+                              WHERE s.id_node=n.id AND s.scanned <= $1
+                              ORDER BY s.scanned DESC
+                              LIMIT 1)
+            WHERE n.seen_first <= $1
+            GROUP BY n.id
+            ORDER BY n.id"#,
+        at)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| GraphNode {
+            id: r.id,
+            public_addr: r.public_addr,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            pins: r.pins,
+        })
+        .collect())
+}
+
+pub async fn get_graph_edges(
+    conn: &Pool<Postgres>,
+    at: Option<DateTime<Utc>>,
+    active: Option<bool>,
+) -> anyhow::Result<Vec<GraphEdge>> {
+    let at = match at {
+        Some(v) => v,
+        None => {
+            let r = query!("SELECT id_left, id_right, active, seen_first, seen_last, latency_ms FROM peer
+                    WHERE $1::boolean IS NULL OR active=$1
+                    ORDER BY id_left, id_right",
+                active)
+                .fetch_all(conn)
+                .await?;
+
+            return Ok(r.into_iter()
+                .map(|r| GraphEdge {
+                    id_left: r.id_left,
+                    id_right: r.id_right,
+                    active: r.active,
+                    seen_first: r.seen_first,
+                    seen_last: r.seen_last,
+                    latency_ms: r.latency_ms,
+                })
+                .collect());
+        }
+    };
+
+    // The edges id_left had by then, active if its last scan at or before the time saw them. An edge can have
+    // been observed on several addresses in the same scan.
+    let r = query!(r#"SELECT p.id_left, p.id_right, COUNT(o.id_left) > 0 AS "active!", p.seen_first, p.seen_last,
+                MIN(o.latency_ms) AS latency_ms
+            FROM (SELECT id_left, MAX(observed) AS observed
+                  FROM (SELECT id_left, observed FROM peer_observation WHERE observed <= $1
+                        UNION ALL
+                        SELECT id_node, scanned FROM node_scan WHERE scanned <= $1) AS s
+                  GROUP BY id_left) AS l
+            JOIN peer AS p ON p.id_left=l.id_left AND p.seen_first <= $1
+            LEFT JOIN peer_observation AS o ON o.id_left=p.id_left AND o.id_right=p.id_right AND o.observed=l.observed
+            GROUP BY p.id_left, p.id_right
+            HAVING $2::boolean IS NULL OR (COUNT(o.id_left) > 0)=$2
+            ORDER BY p.id_left, p.id_right"#,
+        at, active)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| GraphEdge {
+            id_left: r.id_left,
+            id_right: r.id_right,
+            active: r.active,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            latency_ms: r.latency_ms,
+        })
+        .collect())
+}
+
+pub async fn export_nodes(
+    conn: &Pool<Postgres>,
+    after: &str,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<Node>> {
+    let r = query!("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+            WHERE id > $1 AND ($2::timestamptz IS NULL OR seen_last >= $2)
+            ORDER BY id
+            LIMIT $3",
+        after, since, limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| Node {
+            id: r.id,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            scan_last: r.scan_last,
+            public_addr: r.public_addr,
+            api_candidate: r.api_candidate,
+        })
+        .collect())
+}
+
+pub async fn export_node_addrs(
+    conn: &Pool<Postgres>,
+    after: (&str, &str),
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<NodeAddrRow>> {
+    let r = query!("SELECT a.id_node, a.addr, a.transport, a.host, a.port, a.relay, a.active FROM node_addr AS a
+            JOIN node AS n ON n.id=a.id_node
+            WHERE (a.id_node, a.addr) > ($1, $2) AND ($3::timestamptz IS NULL OR n.seen_last >= $3)
+            ORDER BY a.id_node, a.addr
+            LIMIT $4",
+        after.0, after.1, since, limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| NodeAddrRow {
+            id_node: r.id_node,
+            addr: r.addr,
+            transport: r.transport,
+            host: r.host,
+            port: r.port,
+            relay: r.relay,
+            active: r.active,
+        })
+        .collect())
+}
+
+pub async fn export_peers(
+    conn: &Pool<Postgres>,
+    after: (&str, &str),
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<PeerRow>> {
+    let r = query!("SELECT id_left, id_right, active, seen_first, seen_last, addr, latency_ms, direction, muxer FROM peer
+            WHERE (id_left, id_right) > ($1, $2) AND ($3::timestamptz IS NULL OR seen_last >= $3)
+            ORDER BY id_left, id_right
+            LIMIT $4",
+        after.0, after.1, since, limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| PeerRow {
+            id_left: r.id_left,
+            id_right: r.id_right,
+            active: r.active,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            addr: r.addr,
+            latency_ms: r.latency_ms,
+            direction: r.direction,
+            muxer: r.muxer,
+        })
+        .collect())
+}
+
+pub async fn export_objects(
+    conn: &Pool<Postgres>,
+    after: &str,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<Object>> {
+    let r = query!(r#"SELECT o.id, o.cid_version, o.codec, o.hash_function, o.size,
+                COALESCE(o.cumulative_size, 0) AS "cumulative_size!", COALESCE(o.num_links, 0) AS "num_links!",
+                COALESCE(o.block_size, 0) AS "block_size!", COALESCE(o.links_size, 0) AS "links_size!",
+                o.file_type, o.file_size, o.file_blocks
+            FROM object AS o
+            WHERE o.id > $1
+              AND ($2::timestamptz IS NULL
+                OR EXISTS (SELECT 1 FROM node_object_pin AS p WHERE p.id_object=o.id AND p.seen_last >= $2))
+            ORDER BY o.id
+            LIMIT $3"#,
+        after, since, limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| Object {
+            id: r.id,
+            cid_version: r.cid_version,
+            codec: r.codec,
+            hash_function: r.hash_function,
+            size: r.size,
+            cumulative_size: r.cumulative_size,
+            num_links: r.num_links,
+            block_size: r.block_size,
+            links_size: r.links_size,
+            file_type: r.file_type,
+            file_size: r.file_size,
+            file_blocks: r.file_blocks,
+        })
+        .collect())
+}
+
+pub async fn export_node_object_pins(
+    conn: &Pool<Postgres>,
+    after: (&str, &str),
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<NodeObjectPinRow>> {
+    let r = query!("SELECT id_node, id_object, pin_type, seen_first, seen_last, active FROM node_object_pin
+            WHERE (id_node, id_object) > ($1, $2) AND ($3::timestamptz IS NULL OR seen_last >= $3)
+            ORDER BY id_node, id_object
+            LIMIT $4",
+        after.0, after.1, since, limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| NodeObjectPinRow {
+            id_node: r.id_node,
+            id_object: r.id_object,
+            pin_type: r.pin_type,
+            seen_first: r.seen_first,
+            seen_last: r.seen_last,
+            active: r.active,
+        })
+        .collect())
+}
+
+pub async fn start_crawl_run(
+    conn: &Pool<Postgres>,
+    kind: &str,
+) -> anyhow::Result<i64> {
+    let r = query!("INSERT INTO crawl_run (kind, started) VALUES ($1, $2) RETURNING id",
+        kind, Utc::now())
+        .fetch_one(conn)
+        .await?;
+
+    Ok(r.id)
+}
+
+pub async fn finish_crawl_run(
+    conn: &Pool<Postgres>,
+    id: i64,
+) -> anyhow::Result<()> {
+    query!("UPDATE crawl_run SET finished=$2 WHERE id=$1",
+        id, Utc::now())
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_crawl_runs(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<Vec<CrawlRun>> {
+    let r = query!("SELECT id, kind, started, finished FROM crawl_run ORDER BY id DESC")
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| CrawlRun {
+            id: r.id,
+            kind: r.kind,
+            started: r.started,
+            finished: r.finished,
+        })
+        .collect())
+}
+
+// Each diff reads what both runs saw and keeps what only one of them did. A node counts as seen by a run if it was
+// scanned or showed up as a peer.
+pub async fn diff_run_nodes(
+    conn: &Pool<Postgres>,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<RunDiffRow>> {
+    let r = query!(r#"SELECT BOOL_OR(id_run=$2) AS "appeared!", id AS "id!", NULL::VARCHAR AS id_other
+            FROM (SELECT id_run, id_node AS id FROM node_scan WHERE id_run IN ($1, $2)
+                  UNION SELECT id_run, id_left FROM peer_observation WHERE id_run IN ($1, $2)
+                  UNION SELECT id_run, id_right FROM peer_observation WHERE id_run IN ($1, $2)) AS t
+            GROUP BY id
+            HAVING COUNT(*)=1
+            ORDER BY 1 DESC, 2"#,
+        from, to)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| RunDiffRow {
+            appeared: r.appeared,
+            id: r.id,
+            id_other: r.id_other,
+        })
+        .collect())
+}
+
+pub async fn diff_run_edges(
+    conn: &Pool<Postgres>,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<RunDiffRow>> {
+    let r = query!(r#"SELECT BOOL_OR(id_run=$2) AS "appeared!", id_left AS "id!", id_right AS "id_other?"
+            FROM (SELECT DISTINCT id_run, id_left, id_right FROM peer_observation WHERE id_run IN ($1, $2)) AS t
+            GROUP BY id_left, id_right
+            HAVING COUNT(*)=1
+            ORDER BY 1 DESC, 2, 3"#,
+        from, to)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| RunDiffRow {
+            appeared: r.appeared,
+            id: r.id,
+            id_other: r.id_other,
+        })
+        .collect())
+}
+
+pub async fn diff_run_apis(
+    conn: &Pool<Postgres>,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<RunDiffRow>> {
+    let r = query!(r#"SELECT BOOL_OR(id_run=$2) AS "appeared!", id_node AS "id!", api_addr AS "id_other?"
+            FROM node_scan
+            WHERE id_run IN ($1, $2) AND api_addr IS NOT NULL
+            GROUP BY id_node, api_addr
+            HAVING COUNT(*)=1
+            ORDER BY 1 DESC, 2, 3"#,
+        from, to)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| RunDiffRow {
+            appeared: r.appeared,
+            id: r.id,
+            id_other: r.id_other,
+        })
+        .collect())
+}
+
+pub async fn diff_run_pins(
+    conn: &Pool<Postgres>,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<RunDiffRow>> {
+    let r = query!(r#"SELECT BOOL_OR(id_run=$2) AS "appeared!", id_node AS "id!", id_object AS "id_other?"
+            FROM pin_observation
+            WHERE id_run IN ($1, $2)
+            GROUP BY id_node, id_object
+            HAVING COUNT(*)=1
+            ORDER BY 1 DESC, 2, 3"#,
+        from, to)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(r.into_iter()
+        .map(|r| RunDiffRow {
+            appeared: r.appeared,
+            id: r.id,
+            id_other: r.id_other,
+        })
+        .collect())
+}
+
+pub async fn get_unseen_addrs(
+    conn: &Pool<Postgres>,
+    addrs: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let rows = query!(r#"SELECT addr AS "addr!" FROM UNNEST($1::TEXT[]) AS t (addr)
+            WHERE NOT EXISTS (SELECT 1 FROM crawl_queue AS q WHERE q.addr=t.addr)"#,
+        addrs)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(rows.into_iter().map(|r| r.addr).collect())
+}
+
+// Queues a node for scanning unless it is already pending or claimed.
+pub async fn add_crawl_job(
+    conn: &Pool<Postgres>,
+    addr: &str,
+    id_node: &str,
+    api_addr: &str,
+) -> anyhow::Result<()> {
+    query!("INSERT INTO crawl_queue (addr, id_node, api_addr, state, attempts, enqueued)
+            VALUES ($1, $2, $3, 'pending', 0, NOW())
+            ON CONFLICT ON CONSTRAINT crawl_queue_pk DO UPDATE SET id_node=$2, api_addr=$3, state='pending', attempts=0, enqueued=NOW()
+            WHERE crawl_queue.state IN ('seen', 'done', 'failed')",
+        addr, id_node, api_addr)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// Claims up to `limit` pending jobs. Locked rows are skipped so that several crawlers can share the queue.
+pub async fn claim_crawl_jobs(
+    conn: &Pool<Postgres>,
+    limit: i64,
+) -> anyhow::Result<Vec<CrawlJob>> {
+    let rows = query!(r#"UPDATE crawl_queue SET state='claimed', attempts=attempts+1, claimed=NOW()
+            WHERE addr IN (
+                SELECT addr FROM crawl_queue
+                WHERE state='pending'
+                ORDER BY enqueued
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING addr, id_node, api_addr AS "api_addr!", attempts"#,
+        limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(rows.into_iter().map(|r| CrawlJob {
+        addr: r.addr,
+        id_node: r.id_node,
+        api_addr: r.api_addr,
+        attempts: r.attempts,
+    }).collect())
+}
+
+pub async fn complete_crawl_job(
+    conn: &Pool<Postgres>,
+    addr: &str,
+) -> anyhow::Result<()> {
+    query!("UPDATE crawl_queue SET state='done', claimed=NULL WHERE addr=$1",
+        addr)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// Puts a job back in the queue, or gives up on it once it has been attempted `max_attempts` times.
+pub async fn fail_crawl_job(
+    conn: &Pool<Postgres>,
+    addr: &str,
+    max_attempts: i32,
+) -> anyhow::Result<()> {
+    query!("UPDATE crawl_queue
+            SET state=CASE WHEN attempts >= $2 THEN 'failed' ELSE 'pending' END, claimed=NULL
+            WHERE addr=$1",
+        addr, max_attempts)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// Returns jobs this crawler claimed but did not finish to the queue.
+pub async fn release_crawl_jobs(
+    conn: &Pool<Postgres>,
+    addrs: &[String],
+) -> anyhow::Result<u64> {
+    let r = query!("UPDATE crawl_queue SET state='pending', claimed=NULL
+            WHERE addr = ANY($1) AND state='claimed'",
+        addrs)
+        .execute(conn)
+        .await?;
+
+    Ok(r.rows_affected())
+}
+
+pub async fn count_open_crawl_jobs(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<i64> {
+    let r = query!(r#"SELECT COUNT(*) AS "count!" FROM crawl_queue WHERE state IN ('pending', 'claimed')"#)
+        .fetch_one(conn)
+        .await?;
+
+    Ok(r.count)
+}
+
+// Returns jobs claimed by a crawler that died or stalled to the queue.
+pub async fn release_stale_crawl_jobs(
+    conn: &Pool<Postgres>,
+    claim_timeout: Duration,
+) -> anyhow::Result<u64> {
+    let interval = PgInterval::try_from(claim_timeout).unwrap();
+
+    let r = query!("UPDATE crawl_queue SET state='pending', claimed=NULL
+            WHERE state='claimed' AND NOW() - claimed > $1",
+        interval)
+        .execute(conn)
+        .await?;
+
+    Ok(r.rows_affected())
+}
+
+pub async fn clear_crawl_queue(
+    conn: &Pool<Postgres>,
+) -> anyhow::Result<()> {
+    query!("DELETE FROM crawl_queue")
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// Rows per multi-row INSERT when writing a scan batch
+const BATCH_SIZE: usize = 5000;
+
+pub async fn write_scan(
+    conn: &Pool<Postgres>,
+    batch: &ScanBatch,
+) -> anyhow::Result<()> {
+    let mut tx = conn.begin().await?;
+
+    query!("INSERT INTO node (id, seen_first, seen_last, scan_last)
+            VALUES ($1, $2, $2, $2)
+            ON CONFLICT ON CONSTRAINT node_pk DO UPDATE SET seen_last=$2, scan_last=$2",
+        batch.id_node, batch.scan_time)
+        .execute(&mut tx)
+        .await?;
+
+    query!("INSERT INTO node_scan (id_run, id_node, scanned, api_addr)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT ON CONSTRAINT node_scan_pk DO UPDATE SET scanned=$3, api_addr=$4",
+        batch.id_run, batch.id_node, batch.scan_time, batch.api_addr)
+        .execute(&mut tx)
+        .await?;
+
+    for node in &batch.nodes {
+        query!("INSERT INTO node (id, seen_first, seen_last, public_addr, api_candidate)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT ON CONSTRAINT node_pk DO UPDATE SET seen_last=$3, public_addr=$4, api_candidate=$5",
+            node.id, node.seen_first, node.seen_last, node.public_addr, node.api_candidate)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for update in &batch.node_updates {
+        query!("UPDATE node
+                SET seen_last=$1, scan_last=$2, public_addr=$3, api_candidate=$4
+                WHERE id=$5",
+            update.seen_last, update.scan_last, update.public_addr, update.api_candidate, update.id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for chunk in batch.referenced_nodes().chunks(BATCH_SIZE) {
+        query!("INSERT INTO node (id, seen_first, seen_last)
+                SELECT id, $2, $2 FROM UNNEST($1::VARCHAR[]) AS t (id)
+                ON CONFLICT ON CONSTRAINT node_pk DO NOTHING",
+            chunk, batch.scan_time)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    // The same object or peer can show up more than once, which a single ON CONFLICT DO UPDATE can't handle. An
+    // object pinned as both v0 and v1 keeps the highest version, as it would across batches.
+    let mut objects: BTreeMap<&str, &Object> = BTreeMap::new();
+    for object in &batch.objects {
+        let v = objects.entry(object.id.as_str()).or_insert(object);
+        if object.cid_version > v.cid_version {
+            *v = object;
+        }
+    }
+    let objects: Vec<&Object> = objects.into_iter().map(|(_, v)| v).collect();
+
+    for chunk in objects.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.id.clone()).collect();
+        let cid_versions: Vec<Option<i16>> = chunk.iter().map(|v| v.cid_version).collect();
+        let codecs: Vec<Option<String>> = chunk.iter().map(|v| v.codec.clone()).collect();
+        let hash_functions: Vec<Option<String>> = chunk.iter().map(|v| v.hash_function.clone()).collect();
+        let sizes: Vec<i64> = chunk.iter().map(|v| v.size).collect();
+        let cumulative_sizes: Vec<i64> = chunk.iter().map(|v| v.cumulative_size).collect();
+        let num_links: Vec<i64> = chunk.iter().map(|v| v.num_links).collect();
+        let block_sizes: Vec<i64> = chunk.iter().map(|v| v.block_size).collect();
+        let links_sizes: Vec<i64> = chunk.iter().map(|v| v.links_size).collect();
+        let file_types: Vec<Option<String>> = chunk.iter().map(|v| v.file_type.clone()).collect();
+        let file_sizes: Vec<Option<i64>> = chunk.iter().map(|v| v.file_size).collect();
+        let file_blocks: Vec<Option<i64>> = chunk.iter().map(|v| v.file_blocks).collect();
+
+        // Rows from before object had the full stat get it filled in.
+        query!("INSERT INTO object (id, cid_version, codec, hash_function, size, cumulative_size, num_links, block_size,
+                    links_size, file_type, file_size, file_blocks)
+                SELECT * FROM UNNEST($1::VARCHAR[], $2::SMALLINT[], $3::VARCHAR[], $4::VARCHAR[], $5::BIGINT[], $6::BIGINT[],
+                    $7::BIGINT[], $8::BIGINT[], $9::BIGINT[], $10::VARCHAR[], $11::BIGINT[], $12::BIGINT[])
+                ON CONFLICT ON CONSTRAINT object_pk DO UPDATE
+                    SET cid_version=GREATEST(object.cid_version, excluded.cid_version), codec=excluded.codec,
+                        hash_function=excluded.hash_function, size=excluded.size, cumulative_size=excluded.cumulative_size,
+                        num_links=excluded.num_links,
+                        block_size=excluded.block_size, links_size=excluded.links_size, file_type=excluded.file_type,
+                        file_size=excluded.file_size, file_blocks=excluded.file_blocks",
+            &ids[..], &cid_versions[..] as _, &codecs[..] as _, &hash_functions[..] as _, &sizes[..], &cumulative_sizes[..],
+            &num_links[..], &block_sizes[..], &links_sizes[..], &file_types[..] as _, &file_sizes[..] as _, &file_blocks[..] as _)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    query!("UPDATE node_object_pin SET active=FALSE
+            WHERE id_node=$1 AND (pin_type IS DISTINCT FROM 'indirect' OR $2)",
+        batch.id_node, batch.pins_indirect)
+        .execute(&mut tx)
+        .await?;
+
+    let pins: BTreeMap<&str, &str> = batch.pins.iter().map(|v| (v.id_object.as_str(), v.pin_type.as_str())).collect();
+    let pins: Vec<(&str, &str)> = pins.into_iter().collect();
+
+    for chunk in pins.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.0.to_owned()).collect();
+        let types: Vec<String> = chunk.iter().map(|v| v.1.to_owned()).collect();
+
+        query!("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                SELECT $1, id_object, pin_type, $4, $4, TRUE FROM UNNEST($2::VARCHAR[], $3::VARCHAR[]) AS t (id_object, pin_type)
+                ON CONFLICT ON CONSTRAINT node_object_pin_pk DO UPDATE SET pin_type=excluded.pin_type, seen_last=$4, active=TRUE",
+            batch.id_node, &ids[..], &types[..], batch.scan_time)
+            .execute(&mut tx)
+            .await?;
+
+        query!("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                SELECT $1, $2, id_object, pin_type FROM UNNEST($3::VARCHAR[], $4::VARCHAR[]) AS t (id_object, pin_type)
+                ON CONFLICT ON CONSTRAINT pin_observation_pk DO UPDATE SET pin_type=excluded.pin_type",
+            batch.id_run, batch.id_node, &ids[..], &types[..])
+            .execute(&mut tx)
+            .await?;
+    }
+
+    let pins: BTreeMap<&str, &str> = batch.pins_without_stat.iter().map(|v| (v.id_object.as_str(), v.pin_type.as_str())).collect();
+    let pins: Vec<(&str, &str)> = pins.into_iter().collect();
+
+    for chunk in pins.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.0.to_owned()).collect();
+        let types: Vec<String> = chunk.iter().map(|v| v.1.to_owned()).collect();
+
+        query!("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                SELECT $1, id_object, pin_type, $4, $4, TRUE FROM UNNEST($2::VARCHAR[], $3::VARCHAR[]) AS t (id_object, pin_type)
+                WHERE EXISTS (SELECT 1 FROM object WHERE id=t.id_object)
+                ON CONFLICT ON CONSTRAINT node_object_pin_pk DO UPDATE SET pin_type=excluded.pin_type, seen_last=$4, active=TRUE",
+            batch.id_node, &ids[..], &types[..], batch.scan_time)
+            .execute(&mut tx)
+            .await?;
+
+        query!("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                SELECT $1, $2, id_object, pin_type FROM UNNEST($3::VARCHAR[], $4::VARCHAR[]) AS t (id_object, pin_type)
+                WHERE EXISTS (SELECT 1 FROM object WHERE id=t.id_object)
+                ON CONFLICT ON CONSTRAINT pin_observation_pk DO UPDATE SET pin_type=excluded.pin_type",
+            batch.id_run, batch.id_node, &ids[..], &types[..])
+            .execute(&mut tx)
+            .await?;
+    }
+
+    let links: BTreeSet<(&str, &str, &str)> = batch.links.iter()
+        .map(|v| (v.id_parent.as_str(), v.id_child.as_str(), v.name.as_str()))
+        .collect();
+    let links: Vec<(&str, &str, &str)> = links.into_iter().collect();
+
+    for chunk in links.chunks(BATCH_SIZE) {
+        let parents: Vec<String> = chunk.iter().map(|v| v.0.to_owned()).collect();
+        let children: Vec<String> = chunk.iter().map(|v| v.1.to_owned()).collect();
+        let names: Vec<String> = chunk.iter().map(|v| v.2.to_owned()).collect();
+
+        query!("INSERT INTO object_link (id_parent, id_child, name)
+                SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::VARCHAR[])
+                ON CONFLICT ON CONSTRAINT object_link_pk DO NOTHING",
+            &parents[..], &children[..], &names[..])
+            .execute(&mut tx)
+            .await?;
+    }
+
+    query!("UPDATE peer SET active=FALSE WHERE id_left=$1",
+        batch.id_node)
+        .execute(&mut tx)
+        .await?;
+
+    for chunk in batch.peers.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.id_right.clone()).collect();
+        let addrs: Vec<String> = chunk.iter().map(|v| v.addr.clone()).collect();
+        let latencies: Vec<Option<i32>> = chunk.iter().map(|v| v.latency_ms).collect();
+        let directions: Vec<Option<String>> = chunk.iter().map(|v| v.direction.clone()).collect();
+        let muxers: Vec<Option<String>> = chunk.iter().map(|v| v.muxer.clone()).collect();
+        let streams: Vec<i32> = chunk.iter().map(|v| v.streams).collect();
+
+        query!("INSERT INTO peer_observation (id_left, id_right, observed, addr, latency_ms, direction, muxer, streams, id_run)
+                SELECT $1, id_right, $2, addr, latency_ms, direction, muxer, streams, $9
+                FROM UNNEST($3::VARCHAR[], $4::VARCHAR[], $5::INT[], $6::VARCHAR[], $7::VARCHAR[], $8::INT[])
+                    AS t (id_right, addr, latency_ms, direction, muxer, streams)
+                ON CONFLICT ON CONSTRAINT peer_observation_pk DO NOTHING",
+            batch.id_node, batch.scan_time, &ids[..], &addrs[..], &latencies[..] as _, &directions[..] as _,
+            &muxers[..] as _, &streams[..], batch.id_run)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    // A peer connected more than once gets the last connection on its edge.
+    let peers: BTreeMap<&str, &Peer> = batch.peers.iter().map(|v| (v.id_right.as_str(), v)).collect();
+    let peers: Vec<&Peer> = peers.into_iter().map(|(_, v)| v).collect();
+
+    for chunk in peers.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.id_right.clone()).collect();
+        let addrs: Vec<String> = chunk.iter().map(|v| v.addr.clone()).collect();
+        let latencies: Vec<Option<i32>> = chunk.iter().map(|v| v.latency_ms).collect();
+        let directions: Vec<Option<String>> = chunk.iter().map(|v| v.direction.clone()).collect();
+        let muxers: Vec<Option<String>> = chunk.iter().map(|v| v.muxer.clone()).collect();
+
+        query!("INSERT INTO peer (id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last, active)
+                SELECT $1, id_right, addr, latency_ms, direction, muxer, $2, $2, TRUE
+                FROM UNNEST($3::VARCHAR[], $4::VARCHAR[], $5::INT[], $6::VARCHAR[], $7::VARCHAR[])
+                    AS t (id_right, addr, latency_ms, direction, muxer)
+                ON CONFLICT ON CONSTRAINT peer_pk DO UPDATE
+                    SET addr=excluded.addr, latency_ms=excluded.latency_ms, direction=excluded.direction,
+                        muxer=excluded.muxer, seen_last=$2, active=TRUE",
+            batch.id_node, batch.scan_time, &ids[..], &addrs[..], &latencies[..] as _, &directions[..] as _,
+            &muxers[..] as _)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    let node_addrs: BTreeMap<(&str, &str), &NodeAddr> = batch.node_addrs.iter().map(|v| ((v.id_node.as_str(), v.addr.as_str()), v)).collect();
+    let node_addrs: Vec<&NodeAddr> = node_addrs.into_iter().map(|(_, v)| v).collect();
+
+    for chunk in node_addrs.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.id_node.clone()).collect();
+        let addrs: Vec<String> = chunk.iter().map(|v| v.addr.clone()).collect();
+        let transports: Vec<Option<String>> = chunk.iter().map(|v| v.transport.clone()).collect();
+        let hosts: Vec<Option<String>> = chunk.iter().map(|v| v.host.clone()).collect();
+        let ports: Vec<Option<i32>> = chunk.iter().map(|v| v.port).collect();
+        let relays: Vec<bool> = chunk.iter().map(|v| v.relay).collect();
+
+        // The nullable columns are passed with `as _`, since query! can't check arrays of Option.
+        query!("INSERT INTO node_addr (id_node, addr, transport, host, port, relay, active)
+                SELECT *, TRUE FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::VARCHAR[], $4::VARCHAR[], $5::INT[], $6::BOOLEAN[])
+                ON CONFLICT ON CONSTRAINT node_addr_pk DO UPDATE
+                    SET transport=excluded.transport, host=excluded.host, port=excluded.port, relay=excluded.relay, active=TRUE",
+            &ids[..], &addrs[..], &transports[..] as _, &hosts[..] as _, &ports[..] as _, &relays[..])
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for chunk in batch.gateways_checked.chunks(BATCH_SIZE) {
+        query!("UPDATE node_gateway SET active=FALSE, check_last=$2, id_run=$3 WHERE id_node=ANY($1)",
+            chunk, batch.scan_time, batch.id_run)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    let gateways: BTreeMap<(&str, &str), &NodeGateway> = batch.gateways.iter().map(|v| ((v.id_node.as_str(), v.url.as_str()), v)).collect();
+    let gateways: Vec<&NodeGateway> = gateways.into_iter().map(|(_, v)| v).collect();
+
+    for chunk in gateways.chunks(BATCH_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|v| v.id_node.clone()).collect();
+        let urls: Vec<String> = chunk.iter().map(|v| v.url.clone()).collect();
+        let latencies: Vec<i32> = chunk.iter().map(|v| v.latency_ms).collect();
+        let subdomains: Vec<bool> = chunk.iter().map(|v| v.subdomain).collect();
+
+        query!("INSERT INTO node_gateway (id_node, url, latency_ms, subdomain, check_last, active, id_run)
+                SELECT id_node, url, latency_ms, subdomain, $5, TRUE, $6
+                FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::INT[], $4::BOOLEAN[]) AS t (id_node, url, latency_ms, subdomain)
+                ON CONFLICT ON CONSTRAINT node_gateway_pk DO UPDATE
+                    SET latency_ms=excluded.latency_ms, subdomain=excluded.subdomain, check_last=$5, active=TRUE, id_run=$6",
+            &ids[..], &urls[..], &latencies[..], &subdomains[..], batch.scan_time, batch.id_run)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for mismatch in &batch.id_mismatches {
+        query!("INSERT INTO node_id_mismatch (id_advertised, id_reported, api_addr, addr, seen_first, seen_last, id_run)
+                VALUES ($1, $2, $3, $4, $5, $5, $6)
+                ON CONFLICT ON CONSTRAINT node_id_mismatch_pk DO UPDATE SET addr=$4, seen_last=$5, id_run=$6",
+            mismatch.id_advertised, mismatch.id_reported, mismatch.api_addr, mismatch.addr, batch.scan_time, batch.id_run)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    // Only a change to the identity itself starts a new row; the latest row just gets the current addresses.
+    for identity in &batch.identities {
+        let r = query!("UPDATE node_identity SET addresses=$6, seen_last=$7, id_run=$8
+                WHERE id=(SELECT id FROM node_identity WHERE id_node=$1 ORDER BY seen_last DESC LIMIT 1)
+                    AND agent_version=$2 AND protocol_version=$3 AND public_key=$4 AND protocols=$5",
+            identity.id_node, identity.agent_version, identity.protocol_version, identity.public_key,
+            &identity.protocols[..], &identity.addresses[..], batch.scan_time, batch.id_run)
+            .execute(&mut tx)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            query!("INSERT INTO node_identity (id_node, agent_version, protocol_version, public_key, protocols, addresses,
+                        seen_first, seen_last, id_run)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $8)",
+                identity.id_node, identity.agent_version, identity.protocol_version, identity.public_key,
+                &identity.protocols[..], &identity.addresses[..], batch.scan_time, batch.id_run)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+
+    // Every address is marked seen first, so that queueing a job can take over its 'seen' row.
+    for chunk in batch.frontier.chunks(BATCH_SIZE) {
+        let addrs: Vec<String> = chunk.iter().map(|v| v.addr.clone()).collect();
+        let ids: Vec<String> = chunk.iter().map(|v| v.id_node.clone()).collect();
+
+        query!("INSERT INTO crawl_queue (addr, id_node, state, attempts, enqueued)
+                SELECT addr, id_node, 'seen', 0, NOW() FROM UNNEST($1::TEXT[], $2::TEXT[]) AS t (addr, id_node)
+                ON CONFLICT ON CONSTRAINT crawl_queue_pk DO NOTHING",
+            &addrs[..], &ids[..])
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for v in &batch.frontier {
+        if let Some(api_addr) = &v.api_addr {
+            query!("INSERT INTO crawl_queue (addr, id_node, api_addr, state, attempts, enqueued)
+                    VALUES ($1, $2, $3, 'pending', 0, NOW())
+                    ON CONFLICT ON CONSTRAINT crawl_queue_pk DO UPDATE SET id_node=$2, api_addr=$3, state='pending', attempts=0, enqueued=NOW()
+                    WHERE crawl_queue.state IN ('seen', 'done', 'failed')",
+                v.addr, v.id_node, api_addr)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use sqlx::postgres::PgPoolOptions;
+
+use crate::db::model;
+use crate::db::schema::{AgentVersionCount, CrawlJob, CrawlRun, GraphEdge, GraphNode, Node, NodeAddr, NodeAddrRow, NodeObjectPin, NodeObjectPinRow, NodePin, NodeUpdate, Object, Peer, PeerEdge, PeerRow, RootPins, RunDiffRow, ScanBatch, ScanError, Stats};
+use crate::db::store::Store;
+
+pub struct PgStore {
+    pool: Pool<Postgres>,
+}
+
+impl PgStore {
+    pub async fn connect(url: &str, max_connections: u32) -> anyhow::Result<PgStore> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
+
+        Ok(PgStore { pool })
+    }
+}
+
+#[async_trait]
+impl Store for PgStore {
+    async fn migrate(&self) -> anyhow::Result<()> {
+        sqlx::migrate!("./migrations/postgres").run(&self.pool).await?;
+        model::normalize_object_ids(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn get_node(&self, id: &str) -> anyhow::Result<Option<Node>> {
+        model::get_node(&self.pool, id).await
+    }
+
+    async fn get_nodes(&self) -> anyhow::Result<Vec<Node>> {
+        model::get_nodes(&self.pool).await
+    }
+
+    async fn add_node(&self, node: &Node) -> anyhow::Result<()> {
+        model::add_node(&self.pool, node).await
+    }
+
+    async fn update_node(&self, update: &NodeUpdate) -> anyhow::Result<()> {
+        model::update_node(&self.pool, update).await
+    }
+
+    async fn set_node_scanned(&self, id: &str, scan_last: DateTime<Utc>) -> anyhow::Result<()> {
+        model::set_node_scanned(&self.pool, id, scan_last).await
+    }
+
+    async fn get_unscanned_nodes(&self, min_time_since_last: Duration) -> anyhow::Result<Vec<Node>> {
+        model::get_unscanned_nodes(&self.pool, min_time_since_last).await
+    }
+
+    async fn deactivate_node_addrs(&self, id_node: &str) -> anyhow::Result<()> {
+        model::deactivate_node_addrs(&self.pool, id_node).await
+    }
+
+    async fn add_node_addr(&self, node_addr: &NodeAddr) -> anyhow::Result<()> {
+        model::add_node_addr(&self.pool, node_addr).await
+    }
+
+    async fn deactivate_node_peers(&self, id_node: &str) -> anyhow::Result<()> {
+        model::deactivate_node_peers(&self.pool, id_node).await
+    }
+
+    async fn add_peer(&self, peer: &Peer) -> anyhow::Result<()> {
+        model::add_peer(&self.pool, peer).await
+    }
+
+    async fn add_object(&self, object: &Object) -> anyhow::Result<()> {
+        model::add_object(&self.pool, object).await
+    }
+
+    async fn add_node_object_pin(&self, node_object_pin: &NodeObjectPin) -> anyhow::Result<()> {
+        model::add_node_object_pin(&self.pool, node_object_pin).await
+    }
+
+    async fn write_scan(&self, batch: &ScanBatch) -> anyhow::Result<()> {
+        model::write_scan(&self.pool, batch).await
+    }
+
+    async fn add_scan_error(&self, error: &ScanError) -> anyhow::Result<()> {
+        model::add_scan_error(&self.pool, error).await
+    }
+
+    async fn get_stats(&self) -> anyhow::Result<Stats> {
+        model::get_stats(&self.pool).await
+    }
+
+    async fn get_agent_versions(&self) -> anyhow::Result<Vec<AgentVersionCount>> {
+        model::get_agent_versions(&self.pool).await
+    }
+
+    async fn get_roots_containing(&self, id_object: &str) -> anyhow::Result<Vec<RootPins>> {
+        model::get_roots_containing(&self.pool, id_object).await
+    }
+
+    async fn get_object(&self, id: &str) -> anyhow::Result<Option<Object>> {
+        model::get_object(&self.pool, id).await
+    }
+
+    async fn get_public_nodes(&self, limit: i64, offset: i64) -> anyhow::Result<Vec<Node>> {
+        model::get_public_nodes(&self.pool, limit, offset).await
+    }
+
+    async fn get_node_peers(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<PeerEdge>> {
+        model::get_node_peers(&self.pool, id_node, limit, offset).await
+    }
+
+    async fn get_node_pins(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        model::get_node_pins(&self.pool, id_node, limit, offset).await
+    }
+
+    async fn get_providers(&self, id_object: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        model::get_providers(&self.pool, id_object, limit, offset).await
+    }
+
+    async fn get_graph_nodes(&self, at: Option<DateTime<Utc>>) -> anyhow::Result<Vec<GraphNode>> {
+        model::get_graph_nodes(&self.pool, at).await
+    }
+
+    async fn get_graph_edges(&self, at: Option<DateTime<Utc>>, active: Option<bool>) -> anyhow::Result<Vec<GraphEdge>> {
+        model::get_graph_edges(&self.pool, at, active).await
+    }
+
+    async fn export_nodes(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Node>> {
+        model::export_nodes(&self.pool, after, since, limit).await
+    }
+
+    async fn export_node_addrs(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeAddrRow>> {
+        model::export_node_addrs(&self.pool, after, since, limit).await
+    }
+
+    async fn export_peers(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<PeerRow>> {
+        model::export_peers(&self.pool, after, since, limit).await
+    }
+
+    async fn export_objects(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Object>> {
+        model::export_objects(&self.pool, after, since, limit).await
+    }
+
+    async fn export_node_object_pins(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeObjectPinRow>> {
+        model::export_node_object_pins(&self.pool, after, since, limit).await
+    }
+
+    async fn start_crawl_run(&self, kind: &str) -> anyhow::Result<i64> {
+        model::start_crawl_run(&self.pool, kind).await
+    }
+
+    async fn finish_crawl_run(&self, id: i64) -> anyhow::Result<()> {
+        model::finish_crawl_run(&self.pool, id).await
+    }
+
+    async fn get_crawl_runs(&self) -> anyhow::Result<Vec<CrawlRun>> {
+        model::get_crawl_runs(&self.pool).await
+    }
+
+    async fn diff_run_nodes(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        model::diff_run_nodes(&self.pool, from, to).await
+    }
+
+    async fn diff_run_edges(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        model::diff_run_edges(&self.pool, from, to).await
+    }
+
+    async fn diff_run_apis(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        model::diff_run_apis(&self.pool, from, to).await
+    }
+
+    async fn diff_run_pins(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        model::diff_run_pins(&self.pool, from, to).await
+    }
+
+    async fn get_unseen_addrs(&self, addrs: &[String]) -> anyhow::Result<Vec<String>> {
+        model::get_unseen_addrs(&self.pool, addrs).await
+    }
+
+    async fn add_crawl_job(&self, addr: &str, id_node: &str, api_addr: &str) -> anyhow::Result<()> {
+        model::add_crawl_job(&self.pool, addr, id_node, api_addr).await
+    }
+
+    async fn claim_crawl_jobs(&self, limit: i64) -> anyhow::Result<Vec<CrawlJob>> {
+        model::claim_crawl_jobs(&self.pool, limit).await
+    }
+
+    async fn complete_crawl_job(&self, addr: &str) -> anyhow::Result<()> {
+        model::complete_crawl_job(&self.pool, addr).await
+    }
+
+    async fn fail_crawl_job(&self, addr: &str, max_attempts: i32) -> anyhow::Result<()> {
+        model::fail_crawl_job(&self.pool, addr, max_attempts).await
+    }
+
+    async fn release_crawl_jobs(&self, addrs: &[String]) -> anyhow::Result<u64> {
+        model::release_crawl_jobs(&self.pool, addrs).await
+    }
+
+    async fn count_open_crawl_jobs(&self) -> anyhow::Result<i64> {
+        model::count_open_crawl_jobs(&self.pool).await
+    }
+
+    async fn release_stale_crawl_jobs(&self, claim_timeout: Duration) -> anyhow::Result<u64> {
+        model::release_stale_crawl_jobs(&self.pool, claim_timeout).await
+    }
+
+    async fn clear_crawl_queue(&self) -> anyhow::Result<()> {
+        model::clear_crawl_queue(&self.pool).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
@@ -1,11 +1,19 @@
+use std::collections::BTreeSet;
+
 use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
 
+#[derive(Clone, FromRow, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject), graphql(complex))]
 pub struct Node {
     pub id: String,
     pub seen_first: DateTime<Utc>,
     pub seen_last: DateTime<Utc>,
     pub scan_last: Option<DateTime<Utc>>,
     pub public_addr: Option<String>,
+    // The probe candidate public_addr was found with, e.g. 443/https
+    pub api_candidate: Option<String>,
 }
 
 pub struct NodeUpdate {
@@ -13,24 +21,337 @@ pub struct NodeUpdate {
     pub seen_last: DateTime<Utc>,
     pub scan_last: Option<DateTime<Utc>>,
     pub public_addr: Option<String>,
+    pub api_candidate: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct NodeAddr {
     pub id_node: String,
     pub addr: String,
+    // Parsed from addr. transport is the outermost one, e.g. quic-v1 or webtransport rather than udp.
+    pub transport: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<i32>,
+    pub relay: bool,
 }
 
+// A connection id_left reported in swarm/peers
+#[derive(Clone)]
 pub struct Peer {
     pub id_left: String,
     pub id_right: String,
+    // The remote address of the connection
+    pub addr: String,
+    pub latency_ms: Option<i32>,
+    // inbound or outbound, from id_left's side
+    pub direction: Option<String>,
+    pub muxer: Option<String>,
+    pub streams: i32,
 }
 
+#[derive(Clone, FromRow)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject), graphql(complex))]
 pub struct Object {
+    // CIDv1 in base32, or as pin/ls reported it if it couldn't be parsed, in which case the CID fields are None
     pub id: String,
+    // The highest CID version the object has been pinned under. The ID is the same whichever version a node pins
+    // it as, so this only ever goes up: 0 means every pin seen so far used CIDv0.
+    pub cid_version: Option<i16>,
+    pub codec: Option<String>,
+    pub hash_function: Option<String>,
+    // DataSize from object/stat, which is only the root block's data
     pub size: i64,
+    pub cumulative_size: i64,
+    pub num_links: i64,
+    pub block_size: i64,
+    pub links_size: i64,
+    // From files/stat, which only works for UnixFS objects. file_type is file, directory or symlink.
+    pub file_type: Option<String>,
+    pub file_size: Option<i64>,
+    pub file_blocks: Option<i64>,
+}
+
+// A link from one block of a pinned DAG to another. name is empty for unnamed links.
+#[derive(Clone)]
+pub struct ObjectLink {
+    pub id_parent: String,
+    pub id_child: String,
+    pub name: String,
 }
 
+#[derive(Clone)]
 pub struct NodeObjectPin {
     pub id_node: String,
     pub id_object: String,
+    // recursive, direct or indirect
+    pub pin_type: String,
+}
+
+// An active edge, as served by the API. The connection details are NULL for edges last seen before they were
+// recorded.
+#[derive(FromRow, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject), graphql(complex))]
+pub struct PeerEdge {
+    pub id_left: String,
+    pub id_right: String,
+    pub addr: Option<String>,
+    pub latency_ms: Option<i32>,
+    pub direction: Option<String>,
+    pub muxer: Option<String>,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+}
+
+// An active pin, as served by the API
+#[derive(FromRow, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject), graphql(complex, name = "Pin"))]
+pub struct NodePin {
+    pub id_node: String,
+    pub id_object: String,
+    pub pin_type: Option<String>,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+}
+
+// Rows as written by `export tables`, with every column of their table
+#[derive(FromRow)]
+pub struct NodeAddrRow {
+    pub id_node: String,
+    pub addr: String,
+    pub transport: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<i32>,
+    pub relay: bool,
+    pub active: bool,
+}
+
+#[derive(FromRow)]
+pub struct PeerRow {
+    pub id_left: String,
+    pub id_right: String,
+    pub active: bool,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+    pub addr: Option<String>,
+    pub latency_ms: Option<i32>,
+    pub direction: Option<String>,
+    pub muxer: Option<String>,
+}
+
+#[derive(FromRow)]
+pub struct NodeObjectPinRow {
+    pub id_node: String,
+    pub id_object: String,
+    pub pin_type: Option<String>,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+    pub active: bool,
+}
+
+// A node as written by `export graph`. pins counts its active pins, or in a point-in-time graph, the pins listed by
+// its last scan at or before that time. Scans from before crawl runs were recorded listed none.
+#[derive(FromRow)]
+pub struct GraphNode {
+    pub id: String,
+    pub public_addr: Option<String>,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+    pub pins: i64,
+}
+
+// An edge as written by `export graph`. In a point-in-time graph, active is whether id_left's last scan at or
+// before that time saw the edge, and latency_ms is from that scan; seen_first and seen_last are still current.
+#[derive(FromRow)]
+pub struct GraphEdge {
+    pub id_left: String,
+    pub id_right: String,
+    pub active: bool,
+    pub seen_first: DateTime<Utc>,
+    pub seen_last: DateTime<Utc>,
+    pub latency_ms: Option<i32>,
+}
+
+// One crawl or rescan process. finished is None while it runs, or if it died.
+#[derive(Clone, FromRow)]
+pub struct CrawlRun {
+    pub id: i64,
+    pub kind: String,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
+// Something seen in one of two crawl runs but not the other: a node, or a pair such as an edge's two nodes or a
+// pin's node and object.
+#[derive(FromRow)]
+pub struct RunDiffRow {
+    // Whether it is in the later run only, rather than the earlier one only
+    pub appeared: bool,
+    pub id: String,
+    pub id_other: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Stats {
+    pub nodes: i64,
+    pub nodes_public: i64,
+    pub nodes_scanned: i64,
+    pub peers_active: i64,
+    pub objects: i64,
+    pub pins: i64,
+    // Sum of cumulative_size over distinct objects, and over pins, so an object pinned by ten nodes counts ten times
+    pub objects_bytes: i64,
+    pub pins_bytes: i64,
+    pub queue_pending: i64,
+}
+
+#[derive(FromRow)]
+pub struct CrawlJob {
+    pub addr: String,
+    pub id_node: String,
+    pub api_addr: String,
+    pub attempts: i32,
+}
+
+// A swarm address added to crawl_queue by a scan: just marked seen, or queued with the API address to scan
+#[derive(Clone)]
+pub struct FrontierAddr {
+    pub addr: String,
+    pub id_node: String,
+    pub api_addr: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct NodeGateway {
+    pub id_node: String,
+    // Base URL, e.g. http://203.0.113.7:8080
+    pub url: String,
+    pub latency_ms: i32,
+    // Redirects /ipfs/<cid> to <cid>.ipfs.<host>
+    pub subdomain: bool,
+}
+
+// From the node's `id` response. protocols is sorted so that identities compare equal regardless of order.
+#[derive(Clone)]
+pub struct NodeIdentity {
+    pub id_node: String,
+    pub agent_version: String,
+    pub protocol_version: String,
+    pub public_key: String,
+    pub protocols: Vec<String>,
+    pub addresses: Vec<String>,
+}
+
+impl NodeIdentity {
+    // Whether other is the same identity, possibly at different addresses
+    pub fn same_identity(&self, other: &NodeIdentity) -> bool {
+        self.id_node == other.id_node
+            && self.agent_version == other.agent_version
+            && self.protocol_version == other.protocol_version
+            && self.public_key == other.public_key
+            && self.protocols == other.protocols
+    }
+}
+
+#[derive(Clone)]
+pub struct NodeIdMismatch {
+    pub id_advertised: String,
+    pub id_reported: String,
+    pub api_addr: String,
+    pub addr: String,
+}
+
+// A pin root whose DAG contains a given CID, and how many nodes pin it
+pub struct RootPins {
+    pub id_object: String,
+    pub nodes: i64,
+}
+
+pub struct AgentVersionCount {
+    pub agent_version: String,
+    pub nodes: i64,
+}
+
+#[derive(Clone)]
+pub struct ScanError {
+    pub id_node: String,
+    pub addr: String,
+    pub kind: String,
+    pub message: String,
+    pub occurred: DateTime<Utc>,
+    // The crawl run the scan belonged to
+    pub id_run: i64,
+}
+
+// Everything learned from one scan of a node. It is written in a single transaction, so a scan that fails
+// part way leaves nothing behind.
+pub struct ScanBatch {
+    pub id_node: String,
+    pub scan_time: DateTime<Utc>,
+    // The crawl run the scan belongs to, and the API address id_node answered on
+    pub id_run: i64,
+    pub api_addr: Option<String>,
+    // Peers seen for the first time, and changes to peers seen before
+    pub nodes: Vec<Node>,
+    pub node_updates: Vec<NodeUpdate>,
+    // Objects pinned by id_node, and its pins of them. Pins that aren't listed again are deactivated, except
+    // indirect ones when they weren't listed at all.
+    pub objects: Vec<Object>,
+    pub pins: Vec<NodeObjectPin>,
+    pub pins_indirect: bool,
+    // Pins listed by id_node whose object couldn't be stat'd. They stay active if the object was stored by an
+    // earlier scan, and are left out otherwise.
+    pub pins_without_stat: Vec<NodeObjectPin>,
+    // Links found walking down from those objects
+    pub links: Vec<ObjectLink>,
+    // Edges from id_node, one per connection, and the addresses id_node knows its peers by
+    pub peers: Vec<Peer>,
+    pub node_addrs: Vec<NodeAddr>,
+    // Peers whose gateways were checked, and the gateways that answered. The others are deactivated.
+    pub gateways_checked: Vec<String>,
+    pub gateways: Vec<NodeGateway>,
+    // Identities of id_node and of the peers whose API answered
+    pub identities: Vec<NodeIdentity>,
+    pub id_mismatches: Vec<NodeIdMismatch>,
+    // Peer addresses for crawl_queue. Written with the rest of the scan, so that after a failed scan the peers
+    // are checked and probed again on retry.
+    pub frontier: Vec<FrontierAddr>,
+}
+
+impl ScanBatch {
+    pub fn new(id_node: &str, id_run: i64) -> ScanBatch {
+        ScanBatch {
+            id_node: id_node.to_owned(),
+            scan_time: Utc::now(),
+            id_run,
+            api_addr: None,
+            nodes: Vec::new(),
+            node_updates: Vec::new(),
+            objects: Vec::new(),
+            pins: Vec::new(),
+            pins_without_stat: Vec::new(),
+            pins_indirect: false,
+            links: Vec::new(),
+            peers: Vec::new(),
+            node_addrs: Vec::new(),
+            gateways_checked: Vec::new(),
+            gateways: Vec::new(),
+            identities: Vec::new(),
+            id_mismatches: Vec::new(),
+            frontier: Vec::new(),
+        }
+    }
+
+    // Every node the batch's peer edges, addresses, gateways, identities and ID mismatches refer to. Each of them gets at least a stub row
+    // before the edges are written, since not every peer address leads to a node being added.
+    pub fn referenced_nodes(&self) -> Vec<String> {
+        let ids: BTreeSet<&str> = self.peers.iter().map(|v| v.id_right.as_str())
+            .chain(self.node_addrs.iter().map(|v| v.id_node.as_str()))
+            .chain(self.gateways.iter().map(|v| v.id_node.as_str()))
+            .chain(self.identities.iter().map(|v| v.id_node.as_str()))
+            .chain(self.id_mismatches.iter().flat_map(|v| vec![v.id_advertised.as_str(), v.id_reported.as_str()]))
+            .collect();
+
+        ids.into_iter().map(|v| v.to_owned()).collect()
+    }
 }
@@ -0,0 +1,1102 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite, query, query_as};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::types::Json;
+
+use crate::cid::Cid;
+use crate::db::schema::{AgentVersionCount, CrawlJob, CrawlRun, GraphEdge, GraphNode, Node, NodeAddr, NodeAddrRow, NodeGateway, NodeIdMismatch, NodeIdentity, NodeObjectPin, NodeObjectPinRow, NodePin, NodeUpdate, Object, Peer, PeerEdge, PeerRow, RootPins, RunDiffRow, ScanBatch, ScanError, Stats};
+use crate::db::store::Store;
+
+// SQLite has no compile-time checked queries here, since the query! macros are checked against Postgres.
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+    pub async fn connect(url: &str, max_connections: u32) -> anyhow::Result<SqliteStore> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+
+        Ok(SqliteStore { pool })
+    }
+
+    // The data half of migration 0016, as in model::normalize_object_ids
+    async fn normalize_object_ids(&self) -> anyhow::Result<()> {
+        let ids: Vec<(String,)> = query_as("SELECT id FROM object WHERE cid_version IS NULL")
+            .fetch_all(&self.pool)
+            .await?;
+
+        for (old,) in ids {
+            let cid: Cid = match old.parse() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let id = cid.to_string();
+
+            let mut tx = self.pool.begin().await?;
+
+            if id != old {
+                query("INSERT INTO object (id, size, cumulative_size, num_links, block_size, links_size, file_type, file_size,
+                            file_blocks)
+                        SELECT ?2, size, cumulative_size, num_links, block_size, links_size, file_type, file_size, file_blocks
+                        FROM object WHERE id=?1
+                        ON CONFLICT (id) DO NOTHING")
+                    .bind(&old)
+                    .bind(&id)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                        SELECT id_node, ?2, pin_type, seen_first, seen_last, active FROM node_object_pin WHERE id_object=?1
+                        ON CONFLICT (id_node, id_object) DO UPDATE
+                            SET pin_type=COALESCE(node_object_pin.pin_type, excluded.pin_type),
+                                seen_first=MIN(node_object_pin.seen_first, excluded.seen_first),
+                                seen_last=MAX(node_object_pin.seen_last, excluded.seen_last),
+                                active=node_object_pin.active OR excluded.active")
+                    .bind(&old)
+                    .bind(&id)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("INSERT INTO object_link (id_parent, id_child, name)
+                        SELECT CASE WHEN id_parent=?1 THEN ?2 ELSE id_parent END, CASE WHEN id_child=?1 THEN ?2 ELSE id_child END, name
+                        FROM object_link WHERE id_parent=?1 OR id_child=?1
+                        ON CONFLICT (id_parent, id_child, name) DO NOTHING")
+                    .bind(&old)
+                    .bind(&id)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("DELETE FROM object_link WHERE id_parent=?1 OR id_child=?1")
+                    .bind(&old)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                        SELECT id_run, id_node, ?2, pin_type FROM pin_observation WHERE id_object=?1
+                        ON CONFLICT (id_run, id_node, id_object) DO NOTHING")
+                    .bind(&old)
+                    .bind(&id)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("DELETE FROM pin_observation WHERE id_object=?")
+                    .bind(&old)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("DELETE FROM node_object_pin WHERE id_object=?")
+                    .bind(&old)
+                    .execute(&mut tx)
+                    .await?;
+
+                query("DELETE FROM object WHERE id=?")
+                    .bind(&old)
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            query("UPDATE object SET cid_version=?, codec=?, hash_function=? WHERE id=? AND cid_version IS NULL")
+                .bind(cid.version as i16)
+                .bind(cid.codec_name())
+                .bind(cid.hash_function_name())
+                .bind(&id)
+                .execute(&mut tx)
+                .await?;
+
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn cutoff(age: Duration) -> anyhow::Result<DateTime<Utc>> {
+    Ok(Utc::now() - chrono::Duration::from_std(age)?)
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn migrate(&self) -> anyhow::Result<()> {
+        sqlx::migrate!("./migrations/sqlite").run(&self.pool).await?;
+        self.normalize_object_ids().await?;
+
+        Ok(())
+    }
+
+    async fn get_node(&self, id: &str) -> anyhow::Result<Option<Node>> {
+        let r = query_as("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node WHERE id=?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_nodes(&self) -> anyhow::Result<Vec<Node>> {
+        let r = query_as("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+                ORDER BY seen_first")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn add_node(&self, node: &Node) -> anyhow::Result<()> {
+        query("INSERT INTO node (id, seen_first, seen_last, public_addr, api_candidate)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT (id) DO UPDATE SET seen_last=excluded.seen_last, public_addr=excluded.public_addr,
+                    api_candidate=excluded.api_candidate")
+            .bind(&node.id)
+            .bind(node.seen_first)
+            .bind(node.seen_last)
+            .bind(&node.public_addr)
+            .bind(&node.api_candidate)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_node(&self, update: &NodeUpdate) -> anyhow::Result<()> {
+        query("UPDATE node
+                SET seen_last=?, scan_last=?, public_addr=?, api_candidate=?
+                WHERE id=?")
+            .bind(update.seen_last)
+            .bind(update.scan_last)
+            .bind(&update.public_addr)
+            .bind(&update.api_candidate)
+            .bind(&update.id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_node_scanned(&self, id: &str, scan_last: DateTime<Utc>) -> anyhow::Result<()> {
+        query("UPDATE node SET scan_last=? WHERE id=?")
+            .bind(scan_last)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_unscanned_nodes(&self, min_time_since_last: Duration) -> anyhow::Result<Vec<Node>> {
+        let r = query_as("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+                WHERE scan_last < ?")
+            .bind(cutoff(min_time_since_last)?)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn deactivate_node_addrs(&self, id_node: &str) -> anyhow::Result<()> {
+        query("UPDATE node_addr SET active=FALSE WHERE id_node=?")
+            .bind(id_node)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_node_addr(&self, node_addr: &NodeAddr) -> anyhow::Result<()> {
+        query("INSERT INTO node_addr (id_node, addr, transport, host, port, relay, active)
+                VALUES (?, ?, ?, ?, ?, ?, TRUE)
+                ON CONFLICT (id_node, addr) DO UPDATE SET transport=excluded.transport, host=excluded.host,
+                    port=excluded.port, relay=excluded.relay, active=TRUE")
+            .bind(&node_addr.id_node)
+            .bind(&node_addr.addr)
+            .bind(&node_addr.transport)
+            .bind(&node_addr.host)
+            .bind(node_addr.port)
+            .bind(node_addr.relay)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn deactivate_node_peers(&self, id_node: &str) -> anyhow::Result<()> {
+        query("UPDATE peer SET active=FALSE WHERE id_left=?")
+            .bind(id_node)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_peer(&self, peer: &Peer) -> anyhow::Result<()> {
+        query("INSERT INTO peer (id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last, active)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, TRUE)
+                ON CONFLICT (id_left, id_right) DO UPDATE SET addr=excluded.addr, latency_ms=excluded.latency_ms,
+                    direction=excluded.direction, muxer=excluded.muxer, seen_last=excluded.seen_last, active=TRUE")
+            .bind(&peer.id_left)
+            .bind(&peer.id_right)
+            .bind(&peer.addr)
+            .bind(peer.latency_ms)
+            .bind(&peer.direction)
+            .bind(&peer.muxer)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_object(&self, object: &Object) -> anyhow::Result<()> {
+        query("INSERT INTO object (id, cid_version, codec, hash_function, size, cumulative_size, num_links, block_size, links_size,
+                    file_type, file_size, file_blocks)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (id) DO UPDATE SET cid_version=MAX(COALESCE(object.cid_version, excluded.cid_version),
+                        COALESCE(excluded.cid_version, object.cid_version)), codec=excluded.codec,
+                    hash_function=excluded.hash_function, size=excluded.size, cumulative_size=excluded.cumulative_size,
+                    num_links=excluded.num_links, block_size=excluded.block_size, links_size=excluded.links_size,
+                    file_type=excluded.file_type, file_size=excluded.file_size, file_blocks=excluded.file_blocks")
+            .bind(&object.id)
+            .bind(object.cid_version)
+            .bind(&object.codec)
+            .bind(&object.hash_function)
+            .bind(object.size)
+            .bind(object.cumulative_size)
+            .bind(object.num_links)
+            .bind(object.block_size)
+            .bind(object.links_size)
+            .bind(&object.file_type)
+            .bind(object.file_size)
+            .bind(object.file_blocks)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_node_object_pin(&self, node_object_pin: &NodeObjectPin) -> anyhow::Result<()> {
+        query("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                VALUES (?1, ?2, ?3, ?4, ?4, TRUE)
+                ON CONFLICT (id_node, id_object) DO UPDATE SET pin_type=excluded.pin_type, seen_last=excluded.seen_last,
+                    active=TRUE")
+            .bind(&node_object_pin.id_node)
+            .bind(&node_object_pin.id_object)
+            .bind(&node_object_pin.pin_type)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // SQLite statements are cheap inside a transaction, so rows are written one at a time.
+    async fn write_scan(&self, batch: &ScanBatch) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        query("INSERT INTO node (id, seen_first, seen_last, scan_last)
+                VALUES (?1, ?2, ?2, ?2)
+                ON CONFLICT (id) DO UPDATE SET seen_last=excluded.seen_last, scan_last=excluded.scan_last")
+            .bind(&batch.id_node)
+            .bind(batch.scan_time)
+            .execute(&mut tx)
+            .await?;
+
+        query("INSERT INTO node_scan (id_run, id_node, scanned, api_addr)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT (id_run, id_node) DO UPDATE SET scanned=excluded.scanned, api_addr=excluded.api_addr")
+            .bind(batch.id_run)
+            .bind(&batch.id_node)
+            .bind(batch.scan_time)
+            .bind(&batch.api_addr)
+            .execute(&mut tx)
+            .await?;
+
+        for node in &batch.nodes {
+            query("INSERT INTO node (id, seen_first, seen_last, public_addr, api_candidate)
+                    VALUES (?, ?, ?, ?, ?)
+                    ON CONFLICT (id) DO UPDATE SET seen_last=excluded.seen_last, public_addr=excluded.public_addr,
+                        api_candidate=excluded.api_candidate")
+                .bind(&node.id)
+                .bind(node.seen_first)
+                .bind(node.seen_last)
+                .bind(&node.public_addr)
+                .bind(&node.api_candidate)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for update in &batch.node_updates {
+            query("UPDATE node
+                    SET seen_last=?, scan_last=?, public_addr=?, api_candidate=?
+                    WHERE id=?")
+                .bind(update.seen_last)
+                .bind(update.scan_last)
+                .bind(&update.public_addr)
+                .bind(&update.api_candidate)
+                .bind(&update.id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for id in batch.referenced_nodes() {
+            query("INSERT INTO node (id, seen_first, seen_last)
+                    VALUES (?1, ?2, ?2)
+                    ON CONFLICT (id) DO NOTHING")
+                .bind(&id)
+                .bind(batch.scan_time)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for object in &batch.objects {
+            query("INSERT INTO object (id, cid_version, codec, hash_function, size, cumulative_size, num_links, block_size, links_size,
+                        file_type, file_size, file_blocks)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (id) DO UPDATE SET cid_version=MAX(COALESCE(object.cid_version, excluded.cid_version),
+                        COALESCE(excluded.cid_version, object.cid_version)), codec=excluded.codec,
+                        hash_function=excluded.hash_function, size=excluded.size, cumulative_size=excluded.cumulative_size,
+                        num_links=excluded.num_links, block_size=excluded.block_size, links_size=excluded.links_size,
+                        file_type=excluded.file_type, file_size=excluded.file_size, file_blocks=excluded.file_blocks")
+                .bind(&object.id)
+                .bind(object.cid_version)
+                .bind(&object.codec)
+                .bind(&object.hash_function)
+                .bind(object.size)
+                .bind(object.cumulative_size)
+                .bind(object.num_links)
+                .bind(object.block_size)
+                .bind(object.links_size)
+                .bind(&object.file_type)
+                .bind(object.file_size)
+                .bind(object.file_blocks)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        query("UPDATE node_object_pin SET active=FALSE
+                WHERE id_node=? AND (pin_type IS NOT 'indirect' OR ?)")
+            .bind(&batch.id_node)
+            .bind(batch.pins_indirect)
+            .execute(&mut tx)
+            .await?;
+
+        for pin in &batch.pins {
+            query("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                    VALUES (?1, ?2, ?3, ?4, ?4, TRUE)
+                    ON CONFLICT (id_node, id_object) DO UPDATE SET pin_type=excluded.pin_type,
+                        seen_last=excluded.seen_last, active=TRUE")
+                .bind(&batch.id_node)
+                .bind(&pin.id_object)
+                .bind(&pin.pin_type)
+                .bind(batch.scan_time)
+                .execute(&mut tx)
+                .await?;
+
+            query("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT (id_run, id_node, id_object) DO UPDATE SET pin_type=excluded.pin_type")
+                .bind(batch.id_run)
+                .bind(&batch.id_node)
+                .bind(&pin.id_object)
+                .bind(&pin.pin_type)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for pin in &batch.pins_without_stat {
+            query("INSERT INTO node_object_pin (id_node, id_object, pin_type, seen_first, seen_last, active)
+                    SELECT ?1, ?2, ?3, ?4, ?4, TRUE WHERE EXISTS (SELECT 1 FROM object WHERE id=?2)
+                    ON CONFLICT (id_node, id_object) DO UPDATE SET pin_type=excluded.pin_type,
+                        seen_last=excluded.seen_last, active=TRUE")
+                .bind(&batch.id_node)
+                .bind(&pin.id_object)
+                .bind(&pin.pin_type)
+                .bind(batch.scan_time)
+                .execute(&mut tx)
+                .await?;
+
+            query("INSERT INTO pin_observation (id_run, id_node, id_object, pin_type)
+                    SELECT ?1, ?2, ?3, ?4 WHERE EXISTS (SELECT 1 FROM object WHERE id=?3)
+                    ON CONFLICT (id_run, id_node, id_object) DO UPDATE SET pin_type=excluded.pin_type")
+                .bind(batch.id_run)
+                .bind(&batch.id_node)
+                .bind(&pin.id_object)
+                .bind(&pin.pin_type)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for link in &batch.links {
+            query("INSERT INTO object_link (id_parent, id_child, name)
+                    VALUES (?, ?, ?)
+                    ON CONFLICT (id_parent, id_child, name) DO NOTHING")
+                .bind(&link.id_parent)
+                .bind(&link.id_child)
+                .bind(&link.name)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        query("UPDATE peer SET active=FALSE WHERE id_left=?")
+            .bind(&batch.id_node)
+            .execute(&mut tx)
+            .await?;
+
+        // A peer connected more than once ends up with the last connection on its edge.
+        for peer in &batch.peers {
+            query("INSERT INTO peer (id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last, active)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, TRUE)
+                    ON CONFLICT (id_left, id_right) DO UPDATE SET addr=excluded.addr, latency_ms=excluded.latency_ms,
+                        direction=excluded.direction, muxer=excluded.muxer, seen_last=excluded.seen_last, active=TRUE")
+                .bind(&batch.id_node)
+                .bind(&peer.id_right)
+                .bind(&peer.addr)
+                .bind(peer.latency_ms)
+                .bind(&peer.direction)
+                .bind(&peer.muxer)
+                .bind(batch.scan_time)
+                .execute(&mut tx)
+                .await?;
+
+            query("INSERT INTO peer_observation (id_left, id_right, observed, addr, latency_ms, direction, muxer, streams, id_run)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (id_left, id_right, observed, addr) DO NOTHING")
+                .bind(&batch.id_node)
+                .bind(&peer.id_right)
+                .bind(batch.scan_time)
+                .bind(&peer.addr)
+                .bind(peer.latency_ms)
+                .bind(&peer.direction)
+                .bind(&peer.muxer)
+                .bind(peer.streams)
+                .bind(batch.id_run)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for node_addr in &batch.node_addrs {
+            query("INSERT INTO node_addr (id_node, addr, transport, host, port, relay, active)
+                    VALUES (?, ?, ?, ?, ?, ?, TRUE)
+                    ON CONFLICT (id_node, addr) DO UPDATE SET transport=excluded.transport, host=excluded.host,
+                        port=excluded.port, relay=excluded.relay, active=TRUE")
+                .bind(&node_addr.id_node)
+                .bind(&node_addr.addr)
+                .bind(&node_addr.transport)
+                .bind(&node_addr.host)
+                .bind(node_addr.port)
+                .bind(node_addr.relay)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for id in &batch.gateways_checked {
+            query("UPDATE node_gateway SET active=FALSE, check_last=?, id_run=? WHERE id_node=?")
+                .bind(batch.scan_time)
+                .bind(batch.id_run)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for gateway in &batch.gateways {
+            query("INSERT INTO node_gateway (id_node, url, latency_ms, subdomain, check_last, active, id_run)
+                    VALUES (?, ?, ?, ?, ?, TRUE, ?)
+                    ON CONFLICT (id_node, url) DO UPDATE SET latency_ms=excluded.latency_ms, subdomain=excluded.subdomain,
+                        check_last=excluded.check_last, active=TRUE, id_run=excluded.id_run")
+                .bind(&gateway.id_node)
+                .bind(&gateway.url)
+                .bind(gateway.latency_ms)
+                .bind(gateway.subdomain)
+                .bind(batch.scan_time)
+                .bind(batch.id_run)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for mismatch in &batch.id_mismatches {
+            query("INSERT INTO node_id_mismatch (id_advertised, id_reported, api_addr, addr, seen_first, seen_last, id_run)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)
+                    ON CONFLICT (id_advertised, id_reported, api_addr) DO UPDATE SET addr=excluded.addr,
+                        seen_last=excluded.seen_last, id_run=excluded.id_run")
+                .bind(&mismatch.id_advertised)
+                .bind(&mismatch.id_reported)
+                .bind(&mismatch.api_addr)
+                .bind(&mismatch.addr)
+                .bind(batch.scan_time)
+                .bind(batch.id_run)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        // Only a change to the identity itself starts a new row; the latest row just gets the current addresses.
+        for identity in &batch.identities {
+            let r = query("UPDATE node_identity SET addresses=?, seen_last=?, id_run=?
+                    WHERE id=(SELECT id FROM node_identity WHERE id_node=? ORDER BY seen_last DESC LIMIT 1)
+                        AND agent_version=? AND protocol_version=? AND public_key=? AND protocols=?")
+                .bind(Json(&identity.addresses))
+                .bind(batch.scan_time)
+                .bind(batch.id_run)
+                .bind(&identity.id_node)
+                .bind(&identity.agent_version)
+                .bind(&identity.protocol_version)
+                .bind(&identity.public_key)
+                .bind(Json(&identity.protocols))
+                .execute(&mut tx)
+                .await?;
+
+            if r.rows_affected() == 0 {
+                query("INSERT INTO node_identity (id_node, agent_version, protocol_version, public_key, protocols, addresses,
+                            seen_first, seen_last, id_run)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, ?8)")
+                    .bind(&identity.id_node)
+                    .bind(&identity.agent_version)
+                    .bind(&identity.protocol_version)
+                    .bind(&identity.public_key)
+                    .bind(Json(&identity.protocols))
+                    .bind(Json(&identity.addresses))
+                    .bind(batch.scan_time)
+                    .bind(batch.id_run)
+                    .execute(&mut tx)
+                    .await?;
+            }
+        }
+
+        // Every address is marked seen first, so that queueing a job can take over its 'seen' row.
+        for v in &batch.frontier {
+            query("INSERT INTO crawl_queue (addr, id_node, state, attempts, enqueued)
+                    VALUES (?, ?, 'seen', 0, ?)
+                    ON CONFLICT (addr) DO NOTHING")
+                .bind(&v.addr)
+                .bind(&v.id_node)
+                .bind(batch.scan_time)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        for v in &batch.frontier {
+            if let Some(api_addr) = &v.api_addr {
+                query("INSERT INTO crawl_queue (addr, id_node, api_addr, state, attempts, enqueued)
+                        VALUES (?, ?, ?, 'pending', 0, ?)
+                        ON CONFLICT (addr) DO UPDATE SET id_node=excluded.id_node, api_addr=excluded.api_addr,
+                            state='pending', attempts=0, enqueued=excluded.enqueued
+                        WHERE crawl_queue.state IN ('seen', 'done', 'failed')")
+                    .bind(&v.addr)
+                    .bind(&v.id_node)
+                    .bind(api_addr)
+                    .bind(batch.scan_time)
+                    .execute(&mut tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_scan_error(&self, error: &ScanError) -> anyhow::Result<()> {
+        query("INSERT INTO scan_error (id_node, addr, kind, message, occurred, id_run)
+                VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(&error.id_node)
+            .bind(&error.addr)
+            .bind(&error.kind)
+            .bind(&error.message)
+            .bind(error.occurred)
+            .bind(error.id_run)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_stats(&self) -> anyhow::Result<Stats> {
+        let r: (i64, i64, i64, i64, i64, i64, i64, i64, i64) = query_as("SELECT
+                    (SELECT COUNT(*) FROM node),
+                    (SELECT COUNT(*) FROM node WHERE public_addr IS NOT NULL),
+                    (SELECT COUNT(*) FROM node WHERE scan_last IS NOT NULL),
+                    (SELECT COUNT(*) FROM peer WHERE active),
+                    (SELECT COUNT(*) FROM object),
+                    (SELECT COUNT(*) FROM node_object_pin WHERE active),
+                    (SELECT COALESCE(SUM(cumulative_size), 0) FROM object),
+                    (SELECT COALESCE(SUM(o.cumulative_size), 0) FROM node_object_pin AS p JOIN object AS o ON o.id=p.id_object
+                        WHERE p.active),
+                    (SELECT COUNT(*) FROM crawl_queue WHERE state IN ('pending', 'claimed'))")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Stats {
+            nodes: r.0,
+            nodes_public: r.1,
+            nodes_scanned: r.2,
+            peers_active: r.3,
+            objects: r.4,
+            pins: r.5,
+            objects_bytes: r.6,
+            pins_bytes: r.7,
+            queue_pending: r.8,
+        })
+    }
+
+    async fn get_agent_versions(&self) -> anyhow::Result<Vec<AgentVersionCount>> {
+        let r: Vec<(String, i64)> = query_as("SELECT agent_version, COUNT(*) FROM node_identity AS i
+                WHERE seen_last=(SELECT MAX(seen_last) FROM node_identity WHERE id_node=i.id_node)
+                GROUP BY agent_version
+                ORDER BY 2 DESC, 1")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r.into_iter()
+            .map(|(agent_version, nodes)| AgentVersionCount { agent_version, nodes })
+            .collect())
+    }
+
+    async fn get_roots_containing(&self, id_object: &str) -> anyhow::Result<Vec<RootPins>> {
+        let r: Vec<(String, i64)> = query_as("WITH RECURSIVE ancestor (id) AS (
+                    SELECT ?
+                    UNION
+                    SELECT l.id_parent FROM object_link AS l JOIN ancestor AS a ON l.id_child=a.id
+                )
+                SELECT p.id_object, COUNT(DISTINCT p.id_node)
+                FROM ancestor AS a JOIN node_object_pin AS p ON p.id_object=a.id AND p.active
+                GROUP BY p.id_object
+                ORDER BY 2 DESC, 1")
+            .bind(id_object)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r.into_iter()
+            .map(|(id_object, nodes)| RootPins { id_object, nodes })
+            .collect())
+    }
+
+    async fn get_object(&self, id: &str) -> anyhow::Result<Option<Object>> {
+        let r = query_as("SELECT id, cid_version, codec, hash_function, size,
+                    COALESCE(cumulative_size, 0) AS cumulative_size, COALESCE(num_links, 0) AS num_links,
+                    COALESCE(block_size, 0) AS block_size, COALESCE(links_size, 0) AS links_size,
+                    file_type, file_size, file_blocks
+                FROM object WHERE id=?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_public_nodes(&self, limit: i64, offset: i64) -> anyhow::Result<Vec<Node>> {
+        let r = query_as("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+                WHERE public_addr IS NOT NULL
+                ORDER BY id
+                LIMIT ? OFFSET ?")
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_node_peers(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<PeerEdge>> {
+        let r = query_as("SELECT id_left, id_right, addr, latency_ms, direction, muxer, seen_first, seen_last FROM peer
+                WHERE id_left=? AND active
+                ORDER BY id_right
+                LIMIT ? OFFSET ?")
+            .bind(id_node)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_node_pins(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        let r = query_as("SELECT id_node, id_object, pin_type, seen_first, seen_last FROM node_object_pin
+                WHERE id_node=? AND active
+                ORDER BY id_object
+                LIMIT ? OFFSET ?")
+            .bind(id_node)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_providers(&self, id_object: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>> {
+        let r = query_as("SELECT id_node, id_object, pin_type, seen_first, seen_last FROM node_object_pin
+                WHERE id_object=? AND active
+                ORDER BY id_node
+                LIMIT ? OFFSET ?")
+            .bind(id_object)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_graph_nodes(&self, at: Option<DateTime<Utc>>) -> anyhow::Result<Vec<GraphNode>> {
+        let r = match at {
+            None => query_as("SELECT n.id, n.public_addr, n.seen_first, n.seen_last, COUNT(p.id_object) AS pins
+                    FROM node AS n
+                    LEFT JOIN node_object_pin AS p ON p.id_node=n.id AND p.active
+                    GROUP BY n.id
+                    ORDER BY n.id")
+                .fetch_all(&self.pool)
+                .await?,
+            // The pins listed by the node's last scan at or before the time
+            Some(at) => query_as("SELECT n.id, n.public_addr, n.seen_first, n.seen_last, COUNT(o.id_object) AS pins
+                    FROM node AS n
+                    LEFT JOIN pin_observation AS o ON o.id_node=n.id
+                        AND o.id_run=(SELECT s.id_run FROM node_scan AS s
+                                      WHERE s.id_node=n.id AND s.scanned <= ?1
+                                      ORDER BY s.scanned DESC
+                                      LIMIT 1)
+                    WHERE n.seen_first <= ?1
+                    GROUP BY n.id
+                    ORDER BY n.id")
+                .bind(at)
+                .fetch_all(&self.pool)
+                .await?,
+        };
+
+        Ok(r)
+    }
+
+    async fn get_graph_edges(&self, at: Option<DateTime<Utc>>, active: Option<bool>) -> anyhow::Result<Vec<GraphEdge>> {
+        let r = match at {
+            None => query_as("SELECT id_left, id_right, active, seen_first, seen_last, latency_ms FROM peer
+                    WHERE ?1 IS NULL OR active=?1
+                    ORDER BY id_left, id_right")
+                .bind(active)
+                .fetch_all(&self.pool)
+                .await?,
+            // As in model::get_graph_edges
+            Some(at) => query_as("SELECT p.id_left, p.id_right, COUNT(o.id_left) > 0 AS active, p.seen_first, p.seen_last,
+                        MIN(o.latency_ms) AS latency_ms
+                    FROM (SELECT id_left, MAX(observed) AS observed
+                          FROM (SELECT id_left, observed FROM peer_observation WHERE observed <= ?1
+                                UNION ALL
+                                SELECT id_node, scanned FROM node_scan WHERE scanned <= ?1) AS s
+                          GROUP BY id_left) AS l
+                    JOIN peer AS p ON p.id_left=l.id_left AND p.seen_first <= ?1
+                    LEFT JOIN peer_observation AS o ON o.id_left=p.id_left AND o.id_right=p.id_right AND o.observed=l.observed
+                    GROUP BY p.id_left, p.id_right
+                    HAVING ?2 IS NULL OR (COUNT(o.id_left) > 0)=?2
+                    ORDER BY p.id_left, p.id_right")
+                .bind(at)
+                .bind(active)
+                .fetch_all(&self.pool)
+                .await?,
+        };
+
+        Ok(r)
+    }
+
+    async fn export_nodes(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Node>> {
+        let r = query_as("SELECT id, seen_first, seen_last, scan_last, public_addr, api_candidate FROM node
+                WHERE id > ?1 AND (?2 IS NULL OR seen_last >= ?2)
+                ORDER BY id
+                LIMIT ?3")
+            .bind(after)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn export_node_addrs(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeAddrRow>> {
+        let r = query_as("SELECT a.id_node, a.addr, a.transport, a.host, a.port, a.relay, a.active FROM node_addr AS a
+                JOIN node AS n ON n.id=a.id_node
+                WHERE (a.id_node, a.addr) > (?1, ?2) AND (?3 IS NULL OR n.seen_last >= ?3)
+                ORDER BY a.id_node, a.addr
+                LIMIT ?4")
+            .bind(after.0)
+            .bind(after.1)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn export_peers(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<PeerRow>> {
+        let r = query_as("SELECT id_left, id_right, active, seen_first, seen_last, addr, latency_ms, direction, muxer FROM peer
+                WHERE (id_left, id_right) > (?1, ?2) AND (?3 IS NULL OR seen_last >= ?3)
+                ORDER BY id_left, id_right
+                LIMIT ?4")
+            .bind(after.0)
+            .bind(after.1)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn export_objects(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Object>> {
+        let r = query_as("SELECT o.id, o.cid_version, o.codec, o.hash_function, o.size,
+                    COALESCE(o.cumulative_size, 0) AS cumulative_size, COALESCE(o.num_links, 0) AS num_links,
+                    COALESCE(o.block_size, 0) AS block_size, COALESCE(o.links_size, 0) AS links_size,
+                    o.file_type, o.file_size, o.file_blocks
+                FROM object AS o
+                WHERE o.id > ?1
+                  AND (?2 IS NULL
+                    OR EXISTS (SELECT 1 FROM node_object_pin AS p WHERE p.id_object=o.id AND p.seen_last >= ?2))
+                ORDER BY o.id
+                LIMIT ?3")
+            .bind(after)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn export_node_object_pins(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeObjectPinRow>> {
+        let r = query_as("SELECT id_node, id_object, pin_type, seen_first, seen_last, active FROM node_object_pin
+                WHERE (id_node, id_object) > (?1, ?2) AND (?3 IS NULL OR seen_last >= ?3)
+                ORDER BY id_node, id_object
+                LIMIT ?4")
+            .bind(after.0)
+            .bind(after.1)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn start_crawl_run(&self, kind: &str) -> anyhow::Result<i64> {
+        let r = query("INSERT INTO crawl_run (kind, started) VALUES (?, ?)")
+            .bind(kind)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(r.last_insert_rowid())
+    }
+
+    async fn finish_crawl_run(&self, id: i64) -> anyhow::Result<()> {
+        query("UPDATE crawl_run SET finished=? WHERE id=?")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_crawl_runs(&self) -> anyhow::Result<Vec<CrawlRun>> {
+        let r = query_as("SELECT id, kind, started, finished FROM crawl_run ORDER BY id DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn diff_run_nodes(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let r = query_as("SELECT MAX(id_run=?2) AS appeared, id, NULL AS id_other
+                FROM (SELECT id_run, id_node AS id FROM node_scan WHERE id_run IN (?1, ?2)
+                      UNION SELECT id_run, id_left FROM peer_observation WHERE id_run IN (?1, ?2)
+                      UNION SELECT id_run, id_right FROM peer_observation WHERE id_run IN (?1, ?2))
+                GROUP BY id
+                HAVING COUNT(*)=1
+                ORDER BY 1 DESC, 2")
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn diff_run_edges(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let r = query_as("SELECT MAX(id_run=?2) AS appeared, id_left AS id, id_right AS id_other
+                FROM (SELECT DISTINCT id_run, id_left, id_right FROM peer_observation WHERE id_run IN (?1, ?2))
+                GROUP BY id_left, id_right
+                HAVING COUNT(*)=1
+                ORDER BY 1 DESC, 2, 3")
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn diff_run_apis(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let r = query_as("SELECT MAX(id_run=?2) AS appeared, id_node AS id, api_addr AS id_other
+                FROM node_scan
+                WHERE id_run IN (?1, ?2) AND api_addr IS NOT NULL
+                GROUP BY id_node, api_addr
+                HAVING COUNT(*)=1
+                ORDER BY 1 DESC, 2, 3")
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn diff_run_pins(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>> {
+        let r = query_as("SELECT MAX(id_run=?2) AS appeared, id_node AS id, id_object AS id_other
+                FROM pin_observation
+                WHERE id_run IN (?1, ?2)
+                GROUP BY id_node, id_object
+                HAVING COUNT(*)=1
+                ORDER BY 1 DESC, 2, 3")
+            .bind(from)
+            .bind(to)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(r)
+    }
+
+    async fn get_unseen_addrs(&self, addrs: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut unseen = Vec::new();
+
+        for addr in addrs {
+            let r = query("SELECT 1 FROM crawl_queue WHERE addr=?")
+                .bind(addr)
+                .fetch_optional(&self.pool)
+                .await?;
+
+            if r.is_none() {
+                unseen.push(addr.clone());
+            }
+        }
+
+        Ok(unseen)
+    }
+
+    async fn add_crawl_job(&self, addr: &str, id_node: &str, api_addr: &str) -> anyhow::Result<()> {
+        query("INSERT INTO crawl_queue (addr, id_node, api_addr, state, attempts, enqueued)
+                VALUES (?, ?, ?, 'pending', 0, ?)
+                ON CONFLICT (addr) DO UPDATE SET id_node=excluded.id_node, api_addr=excluded.api_addr,
+                    state='pending', attempts=0, enqueued=excluded.enqueued
+                WHERE crawl_queue.state IN ('seen', 'done', 'failed')")
+            .bind(addr)
+            .bind(id_node)
+            .bind(api_addr)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // SQLite has a single writer, so claiming inside a transaction is enough to keep crawlers apart.
+    async fn claim_crawl_jobs(&self, limit: i64) -> anyhow::Result<Vec<CrawlJob>> {
+        let mut tx = self.pool.begin().await?;
+
+        let jobs: Vec<CrawlJob> = query_as("SELECT addr, id_node, api_addr, attempts + 1 AS attempts FROM crawl_queue
+                WHERE state='pending'
+                ORDER BY enqueued
+                LIMIT ?")
+            .bind(limit)
+            .fetch_all(&mut tx)
+            .await?;
+
+        for job in &jobs {
+            query("UPDATE crawl_queue SET state='claimed', attempts=?, claimed=? WHERE addr=?")
+                .bind(job.attempts)
+                .bind(Utc::now())
+                .bind(&job.addr)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(jobs)
+    }
+
+    async fn complete_crawl_job(&self, addr: &str) -> anyhow::Result<()> {
+        query("UPDATE crawl_queue SET state='done', claimed=NULL WHERE addr=?")
+            .bind(addr)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn fail_crawl_job(&self, addr: &str, max_attempts: i32) -> anyhow::Result<()> {
+        query("UPDATE crawl_queue
+                SET state=CASE WHEN attempts >= ? THEN 'failed' ELSE 'pending' END, claimed=NULL
+                WHERE addr=?")
+            .bind(max_attempts)
+            .bind(addr)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn release_crawl_jobs(&self, addrs: &[String]) -> anyhow::Result<u64> {
+        let mut released = 0;
+
+        let mut tx = self.pool.begin().await?;
+        for addr in addrs {
+            let r = query("UPDATE crawl_queue SET state='pending', claimed=NULL
+                    WHERE addr=? AND state='claimed'")
+                .bind(addr)
+                .execute(&mut tx)
+                .await?;
+
+            released += r.rows_affected();
+        }
+        tx.commit().await?;
+
+        Ok(released)
+    }
+
+    async fn count_open_crawl_jobs(&self) -> anyhow::Result<i64> {
+        let r: (i64,) = query_as("SELECT COUNT(*) FROM crawl_queue WHERE state IN ('pending', 'claimed')")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(r.0)
+    }
+
+    async fn release_stale_crawl_jobs(&self, claim_timeout: Duration) -> anyhow::Result<u64> {
+        let r = query("UPDATE crawl_queue SET state='pending', claimed=NULL
+                WHERE state='claimed' AND claimed < ?")
+            .bind(cutoff(claim_timeout)?)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(r.rows_affected())
+    }
+
+    async fn clear_crawl_queue(&self) -> anyhow::Result<()> {
+        query("DELETE FROM crawl_queue")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
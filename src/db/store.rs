@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::config::{Backend, DatabaseConfig};
+use crate::db::memory::MemoryStore;
+use crate::db::postgres::PgStore;
+use crate::db::schema::{AgentVersionCount, CrawlJob, CrawlRun, GraphEdge, GraphNode, Node, NodeAddr, NodeAddrRow, NodeObjectPin, NodeObjectPinRow, NodePin, NodeUpdate, Object, Peer, PeerEdge, PeerRow, RootPins, RunDiffRow, ScanBatch, ScanError, Stats};
+use crate::db::sqlite::SqliteStore;
+
+// Every persistence operation the crawler and the subcommands need. Implementations must behave like the
+// Postgres schema, including its foreign keys, so that code tested against one backend works against the others.
+#[async_trait]
+pub trait Store: Send + Sync {
+    // Applies the migrations embedded from migrations/<backend> that haven't been applied yet. Applied versions
+    // are tracked in the _sqlx_migrations table.
+    async fn migrate(&self) -> anyhow::Result<()>;
+
+    async fn get_node(&self, id: &str) -> anyhow::Result<Option<Node>>;
+    async fn get_nodes(&self) -> anyhow::Result<Vec<Node>>;
+    async fn add_node(&self, node: &Node) -> anyhow::Result<()>;
+    async fn update_node(&self, update: &NodeUpdate) -> anyhow::Result<()>;
+    async fn set_node_scanned(&self, id: &str, scan_last: DateTime<Utc>) -> anyhow::Result<()>;
+    async fn get_unscanned_nodes(&self, min_time_since_last: Duration) -> anyhow::Result<Vec<Node>>;
+
+    async fn deactivate_node_addrs(&self, id_node: &str) -> anyhow::Result<()>;
+    async fn add_node_addr(&self, node_addr: &NodeAddr) -> anyhow::Result<()>;
+
+    async fn deactivate_node_peers(&self, id_node: &str) -> anyhow::Result<()>;
+    async fn add_peer(&self, peer: &Peer) -> anyhow::Result<()>;
+
+    async fn add_object(&self, object: &Object) -> anyhow::Result<()>;
+    async fn add_node_object_pin(&self, node_object_pin: &NodeObjectPin) -> anyhow::Result<()>;
+
+    // Writes the result of a node scan atomically: the node itself (marked scanned), new and updated peer nodes,
+    // objects and pins, peer edges and addresses, and the peers' crawl_queue rows. The node's other peer edges are
+    // deactivated.
+    async fn write_scan(&self, batch: &ScanBatch) -> anyhow::Result<()>;
+
+    async fn add_scan_error(&self, error: &ScanError) -> anyhow::Result<()>;
+
+    async fn get_stats(&self) -> anyhow::Result<Stats>;
+    // Nodes per agent version, going by each node's latest identity
+    async fn get_agent_versions(&self) -> anyhow::Result<Vec<AgentVersionCount>>;
+    // Pin roots whose DAG contains the object, going by the links in object_link. Includes the object itself if
+    // it is pinned.
+    async fn get_roots_containing(&self, id_object: &str) -> anyhow::Result<Vec<RootPins>>;
+
+    async fn get_object(&self, id: &str) -> anyhow::Result<Option<Object>>;
+
+    // Paginated reads for the HTTP API, in a stable order. Only active edges and pins are included.
+    async fn get_public_nodes(&self, limit: i64, offset: i64) -> anyhow::Result<Vec<Node>>;
+    async fn get_node_peers(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<PeerEdge>>;
+    async fn get_node_pins(&self, id_node: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>>;
+    async fn get_providers(&self, id_object: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<NodePin>>;
+
+    // For `export graph`. With a time, only the nodes first seen by then, and the edges seen by each node's last
+    // scan at or before it. active filters edges by whether they are active now.
+    async fn get_graph_nodes(&self, at: Option<DateTime<Utc>>) -> anyhow::Result<Vec<GraphNode>>;
+    async fn get_graph_edges(&self, at: Option<DateTime<Utc>>, active: Option<bool>) -> anyhow::Result<Vec<GraphEdge>>;
+
+    // For `export tables`: a page of a table in primary key order, after the given key. With a time, only rows seen
+    // since then, going by their node for node_addr and by their pins for object.
+    async fn export_nodes(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Node>>;
+    async fn export_node_addrs(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeAddrRow>>;
+    async fn export_peers(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<PeerRow>>;
+    async fn export_objects(&self, after: &str, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Object>>;
+    async fn export_node_object_pins(&self, after: (&str, &str), since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<NodeObjectPinRow>>;
+
+    // kind is crawl or rescan. Every scan written during the run is tagged with the returned ID.
+    async fn start_crawl_run(&self, kind: &str) -> anyhow::Result<i64>;
+    async fn finish_crawl_run(&self, id: i64) -> anyhow::Result<()>;
+    // Newest first
+    async fn get_crawl_runs(&self) -> anyhow::Result<Vec<CrawlRun>>;
+
+    // For `diff`: what was seen in one of the two runs but not the other. Nodes are the ones scanned or seen as a
+    // peer, edges are (id_left, id_right), public APIs are (node, API address) and pins are (node, object).
+    async fn diff_run_nodes(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>>;
+    async fn diff_run_edges(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>>;
+    async fn diff_run_apis(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>>;
+    async fn diff_run_pins(&self, from: i64, to: i64) -> anyhow::Result<Vec<RunDiffRow>>;
+
+    // The swarm addresses that aren't in crawl_queue yet
+    async fn get_unseen_addrs(&self, addrs: &[String]) -> anyhow::Result<Vec<String>>;
+    async fn add_crawl_job(&self, addr: &str, id_node: &str, api_addr: &str) -> anyhow::Result<()>;
+    async fn claim_crawl_jobs(&self, limit: i64) -> anyhow::Result<Vec<CrawlJob>>;
+    async fn complete_crawl_job(&self, addr: &str) -> anyhow::Result<()>;
+    async fn fail_crawl_job(&self, addr: &str, max_attempts: i32) -> anyhow::Result<()>;
+    async fn release_crawl_jobs(&self, addrs: &[String]) -> anyhow::Result<u64>;
+    async fn count_open_crawl_jobs(&self) -> anyhow::Result<i64>;
+    async fn release_stale_crawl_jobs(&self, claim_timeout: Duration) -> anyhow::Result<u64>;
+    async fn clear_crawl_queue(&self) -> anyhow::Result<()>;
+
+    async fn close(&self);
+}
+
+pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Arc<dyn Store>> {
+    let store = open(config, &config.url).await?;
+
+    if config.auto_migrate {
+        store.migrate().await?;
+    }
+
+    Ok(store)
+}
+
+// Connects with migrate_url, if set, since the crawler's own role usually can't create tables.
+pub async fn connect_for_migration(config: &DatabaseConfig) -> anyhow::Result<Arc<dyn Store>> {
+    open(config, config.migrate_url.as_deref().unwrap_or(&config.url)).await
+}
+
+async fn open(config: &DatabaseConfig, url: &str) -> anyhow::Result<Arc<dyn Store>> {
+    Ok(match config.backend {
+        Backend::Postgres => Arc::new(PgStore::connect(url, config.max_connections).await?),
+        Backend::Sqlite => Arc::new(SqliteStore::connect(url, config.max_connections).await?),
+        Backend::Memory => Arc::new(MemoryStore::default()),
+    })
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::time::Duration;
+
+// Why a scan, or one step of it, failed. The kind is what gets recorded in scan_error.
+#[derive(Debug)]
+pub enum CrawlError {
+    // Nothing answered on the API address
+    Unreachable { addr: String, message: String },
+    // The API answered, but a call returned an error
+    Api { addr: String, call: &'static str, message: String },
+    Timeout { addr: String, after: Duration },
+    MalformedAddr { addr: String, message: String },
+    Storage(anyhow::Error),
+}
+
+impl CrawlError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CrawlError::Unreachable { .. } => "unreachable",
+            CrawlError::Api { .. } => "api",
+            CrawlError::Timeout { .. } => "timeout",
+            CrawlError::MalformedAddr { .. } => "malformed_addr",
+            CrawlError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrawlError::Unreachable { addr, message } => write!(f, "{} is unreachable: {}", addr, message),
+            CrawlError::Api { addr, call, message } => write!(f, "{} failed on {}: {}", call, addr, message),
+            CrawlError::Timeout { addr, after } => write!(f, "{} timed out after {}s", addr, after.as_secs()),
+            CrawlError::MalformedAddr { addr, message } => write!(f, "malformed multiaddr {}: {}", addr, message),
+            CrawlError::Storage(e) => write!(f, "storage: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CrawlError {}
+
+// Everything the stores return is an anyhow error, so `?` on a store call is a storage failure.
+impl From<anyhow::Error> for CrawlError {
+    fn from(e: anyhow::Error) -> CrawlError {
+        CrawlError::Storage(e)
+    }
+}
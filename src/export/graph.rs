@@ -0,0 +1,204 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::db::schema::{GraphEdge, GraphNode};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphMl,
+    Gexf,
+    Dot,
+}
+
+// Which peer edges to export, by whether they are active now
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EdgeFilter {
+    Active,
+    Inactive,
+    All,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "gexf" => Ok(GraphFormat::Gexf),
+            "dot" => Ok(GraphFormat::Dot),
+            _ => Err(anyhow!("unknown graph format '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for EdgeFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(EdgeFilter::Active),
+            "inactive" => Ok(EdgeFilter::Inactive),
+            "all" => Ok(EdgeFilter::All),
+            _ => Err(anyhow!("unknown edge filter '{}'", s)),
+        }
+    }
+}
+
+impl EdgeFilter {
+    // The value of peer.active to match, if any
+    pub fn active(self) -> Option<bool> {
+        match self {
+            EdgeFilter::Active => Some(true),
+            EdgeFilter::Inactive => Some(false),
+            EdgeFilter::All => None,
+        }
+    }
+}
+
+// Writes a directed graph, with an edge from each scanned node to every peer it was connected to
+pub fn write(out: &mut dyn Write, format: GraphFormat, nodes: &[GraphNode], edges: &[GraphEdge]) -> io::Result<()> {
+    match format {
+        GraphFormat::GraphMl => write_graphml(out, nodes, edges),
+        GraphFormat::Gexf => write_gexf(out, nodes, edges),
+        GraphFormat::Dot => write_dot(out, nodes, edges),
+    }
+}
+
+fn write_graphml(out: &mut dyn Write, nodes: &[GraphNode], edges: &[GraphEdge]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(out, r#"  <key id="n_public_addr" for="node" attr.name="public_addr" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="n_seen_first" for="node" attr.name="seen_first" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="n_seen_last" for="node" attr.name="seen_last" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="n_pins" for="node" attr.name="pins" attr.type="long"/>"#)?;
+    writeln!(out, r#"  <key id="e_active" for="edge" attr.name="active" attr.type="boolean"/>"#)?;
+    writeln!(out, r#"  <key id="e_seen_first" for="edge" attr.name="seen_first" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="e_seen_last" for="edge" attr.name="seen_last" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="e_latency_ms" for="edge" attr.name="latency_ms" attr.type="int"/>"#)?;
+    writeln!(out, r#"  <graph id="peers" edgedefault="directed">"#)?;
+
+    for v in nodes {
+        writeln!(out, r#"    <node id="{}">"#, xml_escape(&v.id))?;
+        if let Some(public_addr) = &v.public_addr {
+            writeln!(out, r#"      <data key="n_public_addr">{}</data>"#, xml_escape(public_addr))?;
+        }
+        writeln!(out, r#"      <data key="n_seen_first">{}</data>"#, time(v.seen_first))?;
+        writeln!(out, r#"      <data key="n_seen_last">{}</data>"#, time(v.seen_last))?;
+        writeln!(out, r#"      <data key="n_pins">{}</data>"#, v.pins)?;
+        writeln!(out, r#"    </node>"#)?;
+    }
+
+    for v in edges {
+        writeln!(out, r#"    <edge source="{}" target="{}">"#, xml_escape(&v.id_left), xml_escape(&v.id_right))?;
+        writeln!(out, r#"      <data key="e_active">{}</data>"#, v.active)?;
+        writeln!(out, r#"      <data key="e_seen_first">{}</data>"#, time(v.seen_first))?;
+        writeln!(out, r#"      <data key="e_seen_last">{}</data>"#, time(v.seen_last))?;
+        if let Some(latency_ms) = v.latency_ms {
+            writeln!(out, r#"      <data key="e_latency_ms">{}</data>"#, latency_ms)?;
+        }
+        writeln!(out, r#"    </edge>"#)?;
+    }
+
+    writeln!(out, r#"  </graph>"#)?;
+    writeln!(out, r#"</graphml>"#)
+}
+
+fn write_gexf(out: &mut dyn Write, nodes: &[GraphNode], edges: &[GraphEdge]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#)?;
+    writeln!(out, r#"  <graph defaultedgetype="directed" mode="static">"#)?;
+    writeln!(out, r#"    <attributes class="node">"#)?;
+    writeln!(out, r#"      <attribute id="public_addr" title="public_addr" type="string"/>"#)?;
+    writeln!(out, r#"      <attribute id="seen_first" title="seen_first" type="string"/>"#)?;
+    writeln!(out, r#"      <attribute id="seen_last" title="seen_last" type="string"/>"#)?;
+    writeln!(out, r#"      <attribute id="pins" title="pins" type="long"/>"#)?;
+    writeln!(out, r#"    </attributes>"#)?;
+    writeln!(out, r#"    <attributes class="edge">"#)?;
+    writeln!(out, r#"      <attribute id="active" title="active" type="boolean"/>"#)?;
+    writeln!(out, r#"      <attribute id="seen_first" title="seen_first" type="string"/>"#)?;
+    writeln!(out, r#"      <attribute id="seen_last" title="seen_last" type="string"/>"#)?;
+    writeln!(out, r#"      <attribute id="latency_ms" title="latency_ms" type="integer"/>"#)?;
+    writeln!(out, r#"    </attributes>"#)?;
+
+    writeln!(out, r#"    <nodes>"#)?;
+    for v in nodes {
+        writeln!(out, r#"      <node id="{0}" label="{0}">"#, xml_escape(&v.id))?;
+        writeln!(out, r#"        <attvalues>"#)?;
+        if let Some(public_addr) = &v.public_addr {
+            writeln!(out, r#"          <attvalue for="public_addr" value="{}"/>"#, xml_escape(public_addr))?;
+        }
+        writeln!(out, r#"          <attvalue for="seen_first" value="{}"/>"#, time(v.seen_first))?;
+        writeln!(out, r#"          <attvalue for="seen_last" value="{}"/>"#, time(v.seen_last))?;
+        writeln!(out, r#"          <attvalue for="pins" value="{}"/>"#, v.pins)?;
+        writeln!(out, r#"        </attvalues>"#)?;
+        writeln!(out, r#"      </node>"#)?;
+    }
+    writeln!(out, r#"    </nodes>"#)?;
+
+    writeln!(out, r#"    <edges>"#)?;
+    for (i, v) in edges.iter().enumerate() {
+        writeln!(out, r#"      <edge id="{}" source="{}" target="{}">"#, i, xml_escape(&v.id_left), xml_escape(&v.id_right))?;
+        writeln!(out, r#"        <attvalues>"#)?;
+        writeln!(out, r#"          <attvalue for="active" value="{}"/>"#, v.active)?;
+        writeln!(out, r#"          <attvalue for="seen_first" value="{}"/>"#, time(v.seen_first))?;
+        writeln!(out, r#"          <attvalue for="seen_last" value="{}"/>"#, time(v.seen_last))?;
+        if let Some(latency_ms) = v.latency_ms {
+            writeln!(out, r#"          <attvalue for="latency_ms" value="{}"/>"#, latency_ms)?;
+        }
+        writeln!(out, r#"        </attvalues>"#)?;
+        writeln!(out, r#"      </edge>"#)?;
+    }
+    writeln!(out, r#"    </edges>"#)?;
+
+    writeln!(out, r#"  </graph>"#)?;
+    writeln!(out, r#"</gexf>"#)
+}
+
+fn write_dot(out: &mut dyn Write, nodes: &[GraphNode], edges: &[GraphEdge]) -> io::Result<()> {
+    writeln!(out, "digraph peers {{")?;
+
+    for v in nodes {
+        write!(out, "  \"{}\" [", dot_escape(&v.id))?;
+        if let Some(public_addr) = &v.public_addr {
+            write!(out, "public_addr=\"{}\", ", dot_escape(public_addr))?;
+        }
+        writeln!(out, "seen_first=\"{}\", seen_last=\"{}\", pins={}];", time(v.seen_first), time(v.seen_last), v.pins)?;
+    }
+
+    for v in edges {
+        write!(out, "  \"{}\" -> \"{}\" [active={}, seen_first=\"{}\", seen_last=\"{}\"",
+               dot_escape(&v.id_left), dot_escape(&v.id_right), v.active, time(v.seen_first), time(v.seen_last))?;
+        if let Some(latency_ms) = v.latency_ms {
+            write!(out, ", latency_ms={}", latency_ms)?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    writeln!(out, "}}")
+}
+
+fn time(v: DateTime<Utc>) -> String {
+    v.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            '"' => r.push_str("&quot;"),
+            '\'' => r.push_str("&apos;"),
+            _ => r.push(c),
+        }
+    }
+    r
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,541 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use arrow_array::builder::{BooleanBuilder, Int16Builder, Int32Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::db::schema::{Node, NodeAddrRow, NodeObjectPinRow, Object, PeerRow};
+use crate::db::store::Store;
+
+// Rows read per query. Only one page of a table is held in memory at a time.
+const PAGE_SIZE: i64 = 10_000;
+// Rows per Parquet row group, which the writer buffers before flushing
+const ROW_GROUP_SIZE: usize = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    Parquet,
+    Csv,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    Node,
+    NodeAddr,
+    Peer,
+    Object,
+    NodeObjectPin,
+}
+
+impl Table {
+    pub const ALL: &'static [Table] = &[Table::Node, Table::NodeAddr, Table::Peer, Table::Object, Table::NodeObjectPin];
+
+    fn name(self) -> &'static str {
+        match self {
+            Table::Node => "node",
+            Table::NodeAddr => "node_addr",
+            Table::Peer => "peer",
+            Table::Object => "object",
+            Table::NodeObjectPin => "node_object_pin",
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Table::ALL.iter()
+            .copied()
+            .find(|v| v.name() == s)
+            .ok_or_else(|| anyhow!("unknown table '{}'", s))
+    }
+}
+
+impl FromStr for TableFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(TableFormat::Parquet),
+            "csv" => Ok(TableFormat::Csv),
+            _ => Err(anyhow!("unknown table format '{}'", s)),
+        }
+    }
+}
+
+impl TableFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Parquet => "parquet",
+            TableFormat::Csv => "csv",
+        }
+    }
+}
+
+// Writes each table to <dir>/<table>.<format>, a page at a time.
+pub async fn export(
+    db: &dyn Store,
+    tables: &[Table],
+    dir: &Path,
+    format: TableFormat,
+    since: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    for &table in tables {
+        let path = dir.join(format!("{}.{}", table.name(), format.extension()));
+
+        let rows = match table {
+            Table::Node => export_table::<Node>(db, &path, format, since).await,
+            Table::NodeAddr => export_table::<NodeAddrRow>(db, &path, format, since).await,
+            Table::Peer => export_table::<PeerRow>(db, &path, format, since).await,
+            Table::Object => export_table::<Object>(db, &path, format, since).await,
+            Table::NodeObjectPin => export_table::<NodeObjectPinRow>(db, &path, format, since).await,
+        }.map_err(|e| anyhow!("{}: {}", table.name(), e))?;
+
+        println!("Exported {} rows of {} to {}", rows, table.name(), path.display());
+    }
+
+    Ok(())
+}
+
+async fn export_table<T: Row>(
+    db: &dyn Store,
+    path: &Path,
+    format: TableFormat,
+    since: Option<DateTime<Utc>>,
+) -> anyhow::Result<u64> {
+    let mut writer = Writer::create(path, format, T::COLUMNS)?;
+    let mut after: Option<T> = None;
+    let mut count = 0;
+
+    loop {
+        let rows = T::fetch(db, after.as_ref(), since, PAGE_SIZE).await?;
+        let n = rows.len();
+        if n == 0 {
+            break;
+        }
+
+        writer.write(T::COLUMNS, rows.iter().map(Row::values).collect())?;
+        count += n as u64;
+
+        after = rows.into_iter().last();
+        if (n as i64) < PAGE_SIZE {
+            break;
+        }
+    }
+
+    writer.finish()?;
+
+    Ok(count)
+}
+
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Text,
+    Int16,
+    Int32,
+    Int64,
+    Bool,
+    Time,
+}
+
+struct Column {
+    name: &'static str,
+    typ: ColumnType,
+    nullable: bool,
+}
+
+const fn column(name: &'static str, typ: ColumnType, nullable: bool) -> Column {
+    Column { name, typ, nullable }
+}
+
+enum Value {
+    Null,
+    Text(String),
+    Int(i64),
+    Bool(bool),
+    Time(DateTime<Utc>),
+}
+
+// A table row. fetch reads the page of rows after the given one in primary key order, which is how export_table
+// pages through the table.
+#[async_trait]
+trait Row: Sized + Send + Sync {
+    const COLUMNS: &'static [Column];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>>;
+
+    // In the order of COLUMNS
+    fn values(&self) -> Vec<Value>;
+}
+
+#[async_trait]
+impl Row for Node {
+    const COLUMNS: &'static [Column] = &[
+        column("id", ColumnType::Text, false),
+        column("seen_first", ColumnType::Time, false),
+        column("seen_last", ColumnType::Time, false),
+        column("scan_last", ColumnType::Time, true),
+        column("public_addr", ColumnType::Text, true),
+        column("api_candidate", ColumnType::Text, true),
+    ];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>> {
+        db.export_nodes(after.map_or("", |v| v.id.as_str()), since, limit).await
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (&self.id).into(),
+            self.seen_first.into(),
+            self.seen_last.into(),
+            self.scan_last.into(),
+            (&self.public_addr).into(),
+            (&self.api_candidate).into(),
+        ]
+    }
+}
+
+#[async_trait]
+impl Row for NodeAddrRow {
+    const COLUMNS: &'static [Column] = &[
+        column("id_node", ColumnType::Text, false),
+        column("addr", ColumnType::Text, false),
+        column("transport", ColumnType::Text, true),
+        column("host", ColumnType::Text, true),
+        column("port", ColumnType::Int32, true),
+        column("relay", ColumnType::Bool, false),
+        column("active", ColumnType::Bool, false),
+    ];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>> {
+        db.export_node_addrs(after.map_or(("", ""), |v| (v.id_node.as_str(), v.addr.as_str())), since, limit).await
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (&self.id_node).into(),
+            (&self.addr).into(),
+            (&self.transport).into(),
+            (&self.host).into(),
+            self.port.into(),
+            self.relay.into(),
+            self.active.into(),
+        ]
+    }
+}
+
+#[async_trait]
+impl Row for PeerRow {
+    const COLUMNS: &'static [Column] = &[
+        column("id_left", ColumnType::Text, false),
+        column("id_right", ColumnType::Text, false),
+        column("active", ColumnType::Bool, false),
+        column("seen_first", ColumnType::Time, false),
+        column("seen_last", ColumnType::Time, false),
+        column("addr", ColumnType::Text, true),
+        column("latency_ms", ColumnType::Int32, true),
+        column("direction", ColumnType::Text, true),
+        column("muxer", ColumnType::Text, true),
+    ];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>> {
+        db.export_peers(after.map_or(("", ""), |v| (v.id_left.as_str(), v.id_right.as_str())), since, limit).await
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (&self.id_left).into(),
+            (&self.id_right).into(),
+            self.active.into(),
+            self.seen_first.into(),
+            self.seen_last.into(),
+            (&self.addr).into(),
+            self.latency_ms.into(),
+            (&self.direction).into(),
+            (&self.muxer).into(),
+        ]
+    }
+}
+
+#[async_trait]
+impl Row for Object {
+    const COLUMNS: &'static [Column] = &[
+        column("id", ColumnType::Text, false),
+        column("cid_version", ColumnType::Int16, true),
+        column("codec", ColumnType::Text, true),
+        column("hash_function", ColumnType::Text, true),
+        column("size", ColumnType::Int64, false),
+        column("cumulative_size", ColumnType::Int64, false),
+        column("num_links", ColumnType::Int64, false),
+        column("block_size", ColumnType::Int64, false),
+        column("links_size", ColumnType::Int64, false),
+        column("file_type", ColumnType::Text, true),
+        column("file_size", ColumnType::Int64, true),
+        column("file_blocks", ColumnType::Int64, true),
+    ];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>> {
+        db.export_objects(after.map_or("", |v| v.id.as_str()), since, limit).await
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (&self.id).into(),
+            self.cid_version.map(i64::from).into(),
+            (&self.codec).into(),
+            (&self.hash_function).into(),
+            self.size.into(),
+            self.cumulative_size.into(),
+            self.num_links.into(),
+            self.block_size.into(),
+            self.links_size.into(),
+            (&self.file_type).into(),
+            self.file_size.into(),
+            self.file_blocks.into(),
+        ]
+    }
+}
+
+#[async_trait]
+impl Row for NodeObjectPinRow {
+    const COLUMNS: &'static [Column] = &[
+        column("id_node", ColumnType::Text, false),
+        column("id_object", ColumnType::Text, false),
+        column("pin_type", ColumnType::Text, true),
+        column("seen_first", ColumnType::Time, false),
+        column("seen_last", ColumnType::Time, false),
+        column("active", ColumnType::Bool, false),
+    ];
+
+    async fn fetch(db: &dyn Store, after: Option<&Self>, since: Option<DateTime<Utc>>, limit: i64) -> anyhow::Result<Vec<Self>> {
+        db.export_node_object_pins(after.map_or(("", ""), |v| (v.id_node.as_str(), v.id_object.as_str())), since, limit).await
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (&self.id_node).into(),
+            (&self.id_object).into(),
+            (&self.pin_type).into(),
+            self.seen_first.into(),
+            self.seen_last.into(),
+            self.active.into(),
+        ]
+    }
+}
+
+enum Writer {
+    Csv(BufWriter<File>),
+    Parquet(Box<ArrowWriter<File>>, SchemaRef),
+}
+
+impl Writer {
+    fn create(path: &Path, format: TableFormat, columns: &[Column]) -> anyhow::Result<Writer> {
+        let file = File::create(path)?;
+
+        Ok(match format {
+            TableFormat::Csv => {
+                let mut out = BufWriter::new(file);
+                let header: Vec<&str> = columns.iter().map(|v| v.name).collect();
+                writeln!(out, "{}", header.join(","))?;
+
+                Writer::Csv(out)
+            }
+            TableFormat::Parquet => {
+                let schema = Arc::new(Schema::new(columns.iter()
+                    .map(|v| Field::new(v.name, data_type(v.typ), v.nullable))
+                    .collect::<Vec<_>>()));
+
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .set_max_row_group_size(ROW_GROUP_SIZE)
+                    .build();
+
+                Writer::Parquet(Box::new(ArrowWriter::try_new(file, schema.clone(), Some(props))?), schema)
+            }
+        })
+    }
+
+    fn write(&mut self, columns: &[Column], rows: Vec<Vec<Value>>) -> anyhow::Result<()> {
+        match self {
+            Writer::Csv(out) => {
+                for row in rows {
+                    let fields: Vec<String> = row.into_iter().map(csv_field).collect();
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+            }
+            Writer::Parquet(out, schema) => {
+                // Transpose the rows into one array per column
+                let mut values: Vec<Vec<Value>> = columns.iter().map(|_| Vec::with_capacity(rows.len())).collect();
+                for row in rows {
+                    for (i, v) in row.into_iter().enumerate() {
+                        values[i].push(v);
+                    }
+                }
+
+                let arrays = columns.iter()
+                    .zip(values)
+                    .map(|(column, values)| array(column.typ, values))
+                    .collect();
+
+                out.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Writer::Csv(mut out) => out.flush()?,
+            Writer::Parquet(out, _) => {
+                out.close()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn data_type(typ: ColumnType) -> DataType {
+    match typ {
+        ColumnType::Text => DataType::Utf8,
+        ColumnType::Int16 => DataType::Int16,
+        ColumnType::Int32 => DataType::Int32,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::Bool => DataType::Boolean,
+        ColumnType::Time => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+    }
+}
+
+// Values that don't match the column type are written as nulls; COLUMNS and values() are kept in step by hand.
+fn array(typ: ColumnType, values: Vec<Value>) -> ArrayRef {
+    match typ {
+        ColumnType::Text => {
+            let mut b = StringBuilder::new();
+            for v in values {
+                match v {
+                    Value::Text(s) => b.append_value(s),
+                    _ => b.append_null(),
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Int16 => {
+            let mut b = Int16Builder::with_capacity(values.len());
+            for v in values {
+                b.append_option(v.int().map(|v| v as i16));
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Int32 => {
+            let mut b = Int32Builder::with_capacity(values.len());
+            for v in values {
+                b.append_option(v.int().map(|v| v as i32));
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Int64 => {
+            let mut b = Int64Builder::with_capacity(values.len());
+            for v in values {
+                b.append_option(v.int());
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Bool => {
+            let mut b = BooleanBuilder::with_capacity(values.len());
+            for v in values {
+                b.append_option(match v {
+                    Value::Bool(v) => Some(v),
+                    _ => None,
+                });
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Time => {
+            let mut b = TimestampMicrosecondBuilder::with_capacity(values.len()).with_timezone("UTC");
+            for v in values {
+                b.append_option(match v {
+                    Value::Time(v) => Some(v.timestamp_micros()),
+                    _ => None,
+                });
+            }
+            Arc::new(b.finish())
+        }
+    }
+}
+
+// Quoted only when it has to be
+fn csv_field(v: Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        Value::Text(s) if s.contains(&[',', '"', '\n', '\r'][..]) => format!("\"{}\"", s.replace('"', "\"\"")),
+        Value::Text(s) => s,
+        Value::Int(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Time(v) => v.to_rfc3339(),
+    }
+}
+
+impl Value {
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl From<&String> for Value {
+    fn from(v: &String) -> Self {
+        Value::Text(v.clone())
+    }
+}
+
+impl From<&Option<String>> for Value {
+    fn from(v: &Option<String>) -> Self {
+        v.as_ref().map_or(Value::Null, Value::from)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(v: DateTime<Utc>) -> Self {
+        Value::Time(v)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
@@ -0,0 +1,265 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_graphql::connection::{self, Connection, Edge};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Error, OutputType, Result, Schema};
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use crate::api::StatsCache;
+use crate::cid;
+use crate::config::ApiConfig;
+use crate::db::schema::{Node, NodePin, Object, PeerEdge, Stats};
+use crate::db::store::Store;
+
+// Largest request body accepted at /graphql
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+// api.page_size, which is what a connection field returns and so is costed at when the query doesn't give `first`.
+// Complexity is worked out by plain functions that can't see the schema's data, so it is kept here when the schema
+// is built; a process serves one schema.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy)]
+struct Limits {
+    page_size: i64,
+    max_page_size: i64,
+}
+
+struct Query;
+
+#[async_graphql::Object]
+impl Query {
+    async fn stats(&self, ctx: &Context<'_>) -> Result<Stats> {
+        Ok(ctx.data_unchecked::<Arc<StatsCache>>().get(db(ctx).as_ref()).await?)
+    }
+
+    async fn node(&self, ctx: &Context<'_>, id: String) -> Result<Option<Node>> {
+        Ok(db(ctx).get_node(&id).await?)
+    }
+
+    async fn object(&self, ctx: &Context<'_>, cid: String) -> Result<Option<Object>> {
+        Ok(db(ctx).get_object(&cid::normalize(&cid)).await?)
+    }
+
+    // Nodes with a public API
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn public_nodes(&self, ctx: &Context<'_>, after: Option<String>, first: Option<i32>) -> Result<Connection<i64, Node>> {
+        paginate(ctx, after, first, |limit, offset| db(ctx).get_public_nodes(limit, offset)).await
+    }
+}
+
+#[ComplexObject]
+impl Node {
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn peers(&self, ctx: &Context<'_>, after: Option<String>, first: Option<i32>) -> Result<Connection<i64, PeerEdge>> {
+        paginate(ctx, after, first, |limit, offset| db(ctx).get_node_peers(&self.id, limit, offset)).await
+    }
+
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn pins(&self, ctx: &Context<'_>, after: Option<String>, first: Option<i32>) -> Result<Connection<i64, NodePin>> {
+        paginate(ctx, after, first, |limit, offset| db(ctx).get_node_pins(&self.id, limit, offset)).await
+    }
+}
+
+#[ComplexObject]
+impl PeerEdge {
+    // The node on the other end of the edge
+    async fn peer(&self, ctx: &Context<'_>) -> Result<Option<Node>> {
+        Ok(db(ctx).get_node(&self.id_right).await?)
+    }
+}
+
+#[ComplexObject]
+impl NodePin {
+    async fn node(&self, ctx: &Context<'_>) -> Result<Option<Node>> {
+        Ok(db(ctx).get_node(&self.id_node).await?)
+    }
+
+    // None if the pinned object couldn't be stat'd
+    async fn object(&self, ctx: &Context<'_>) -> Result<Option<Object>> {
+        Ok(db(ctx).get_object(&self.id_object).await?)
+    }
+}
+
+#[ComplexObject]
+impl Object {
+    // Every node with an active pin on the object
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn providers(&self, ctx: &Context<'_>, after: Option<String>, first: Option<i32>) -> Result<Connection<i64, NodePin>> {
+        paginate(ctx, after, first, |limit, offset| db(ctx).get_providers(&self.id, limit, offset)).await
+    }
+}
+
+// POST /graphql, taking the usual {"query": ..., "variables": ...} body. Queries nested deeper than
+// api.graphql_max_depth or costing more than api.graphql_max_complexity are rejected before they run.
+pub fn routes(config: &ApiConfig, db: Arc<dyn Store>, stats: Arc<StatsCache>) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let schema = schema(config, db, stats);
+
+    warp::path!("graphql")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and_then(move |request: async_graphql::Request| {
+            let schema = schema.clone();
+            async move {
+                Ok::<_, Infallible>(reply::json(&schema.execute(request).await).into_response())
+            }
+        })
+}
+
+fn schema(config: &ApiConfig, db: Arc<dyn Store>, stats: Arc<StatsCache>) -> Schema<Query, EmptyMutation, EmptySubscription> {
+    PAGE_SIZE.store(config.page_size.max(0) as usize, Ordering::Relaxed);
+
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(db)
+        .data(stats)
+        .data(Limits { page_size: config.page_size, max_page_size: config.max_page_size })
+        .limit_depth(config.graphql_max_depth)
+        .limit_complexity(config.graphql_max_complexity)
+        .finish()
+}
+
+// The cost of a connection field: its selection once for every row of the page
+fn page_cost(first: Option<i32>, child_complexity: usize) -> usize {
+    first.map(|v| v.max(0) as usize)
+        .unwrap_or_else(|| PAGE_SIZE.load(Ordering::Relaxed))
+        .saturating_mul(child_complexity)
+}
+
+fn db<'a>(ctx: &Context<'a>) -> &'a Arc<dyn Store> {
+    ctx.data_unchecked::<Arc<dyn Store>>()
+}
+
+// Forward pagination over the Store's limit/offset reads. A row's cursor is its offset, so `after` continues
+// from the row after it. One row more than asked for is read to tell whether there is a next page.
+async fn paginate<T, F, Fut>(ctx: &Context<'_>, after: Option<String>, first: Option<i32>, fetch: F) -> Result<Connection<i64, T>>
+    where T: OutputType,
+          F: FnOnce(i64, i64) -> Fut,
+          Fut: Future<Output = anyhow::Result<Vec<T>>> {
+    let limits = ctx.data_unchecked::<Limits>();
+
+    connection::query(after, None, first, None, |after: Option<i64>, _: Option<i64>, first: Option<usize>, _| async move {
+        let offset = after.map(|v| v + 1).unwrap_or(0);
+        let limit = first.map(|v| v as i64).unwrap_or(limits.page_size).min(limits.max_page_size);
+
+        let mut rows = fetch(limit + 1, offset).await?;
+        let has_next_page = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+
+        let mut r = Connection::new(offset > 0, has_next_page);
+        r.edges.extend(rows.into_iter()
+            .enumerate()
+            .map(|(i, v)| Edge::new(offset + i as i64, v)));
+
+        Ok::<_, Error>(r)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::value;
+    use chrono::Utc;
+
+    use super::*;
+    use crate::db::memory::MemoryStore;
+    use crate::db::schema::{Peer, ScanBatch};
+
+    // QmA scanned once, connected to the public nodes QmB and QmC
+    async fn store() -> Arc<dyn Store> {
+        let db = MemoryStore::default();
+        let id_run = db.start_crawl_run("crawl").await.unwrap();
+
+        let mut batch = ScanBatch::new("QmA", id_run);
+        for id in &["QmB", "QmC"] {
+            batch.nodes.push(Node {
+                id: id.to_string(),
+                seen_first: Utc::now(),
+                seen_last: Utc::now(),
+                scan_last: None,
+                public_addr: Some(format!("http://{}.example:5001", id)),
+                api_candidate: Some("5001/http".to_owned()),
+            });
+            batch.peers.push(Peer {
+                id_left: "QmA".to_owned(),
+                id_right: id.to_string(),
+                addr: "/ip4/192.0.2.1/tcp/4001".to_owned(),
+                latency_ms: None,
+                direction: None,
+                muxer: None,
+                streams: 0,
+            });
+        }
+        db.write_scan(&batch).await.unwrap();
+
+        Arc::new(db)
+    }
+
+    async fn execute(config: &ApiConfig, query: &str) -> async_graphql::Response {
+        schema(config, store().await, Arc::new(StatsCache::new(config.stats_ttl()))).execute(query).await
+    }
+
+    #[tokio::test]
+    async fn public_nodes_continue_after_the_cursor() {
+        let config = ApiConfig::default();
+
+        let r = execute(&config, "{ publicNodes(first: 1) { pageInfo { hasNextPage } edges { cursor node { id } } } }").await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+        assert_eq!(r.data, value!({
+            "publicNodes": { "pageInfo": { "hasNextPage": true }, "edges": [{ "cursor": "0", "node": { "id": "QmB" } }] }
+        }));
+
+        let r = execute(&config, r#"{ publicNodes(first: 1, after: "0") { pageInfo { hasNextPage } edges { node { id } } } }"#).await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+        assert_eq!(r.data, value!({
+            "publicNodes": { "pageInfo": { "hasNextPage": false }, "edges": [{ "node": { "id": "QmC" } }] }
+        }));
+    }
+
+    #[tokio::test]
+    async fn pages_are_capped_at_max_page_size() {
+        let config = ApiConfig { max_page_size: 1, ..ApiConfig::default() };
+
+        let r = execute(&config, "{ publicNodes(first: 10) { pageInfo { hasNextPage } edges { node { id } } } }").await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+        assert_eq!(r.data, value!({
+            "publicNodes": { "pageInfo": { "hasNextPage": true }, "edges": [{ "node": { "id": "QmB" } }] }
+        }));
+    }
+
+    #[tokio::test]
+    async fn peers_resolve_to_their_nodes() {
+        let r = execute(&ApiConfig::default(), r#"{ node(id: "QmA") { peers { edges { node { peer { id publicAddr } } } } } }"#).await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+        assert_eq!(r.data, value!({
+            "node": { "peers": { "edges": [
+                { "node": { "peer": { "id": "QmB", "publicAddr": "http://QmB.example:5001" } } },
+                { "node": { "peer": { "id": "QmC", "publicAddr": "http://QmC.example:5001" } } }
+            ] } }
+        }));
+    }
+
+    #[tokio::test]
+    async fn missing_rows_are_null() {
+        let r = execute(&ApiConfig::default(), r#"{ node(id: "QmMissing") { id } }"#).await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+        assert_eq!(r.data, value!({ "node": null }));
+    }
+
+    // Nested pages multiply, so a query that reads little per level can still be rejected
+    #[tokio::test]
+    async fn costly_queries_are_rejected() {
+        let config = ApiConfig { graphql_max_complexity: 1000, ..ApiConfig::default() };
+
+        let cheap = "{ publicNodes(first: 10) { edges { node { peers(first: 10) { edges { node { idRight } } } } } } }";
+        let r = execute(&config, cheap).await;
+        assert!(r.errors.is_empty(), "{:?}", r.errors);
+
+        let costly = "{ publicNodes(first: 100) { edges { node { peers(first: 100) { edges { node { idRight } } } } } } }";
+        let r = execute(&config, costly).await;
+        assert_eq!(r.errors.len(), 1);
+        assert!(r.errors[0].message.contains("complex"), "{}", r.errors[0].message);
+    }
+}
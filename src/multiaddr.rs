@@ -0,0 +1,282 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+// The textual multiaddr protocols IPFS nodes advertise. Without knowing a protocol there's no telling whether the
+// next segment is its value or another protocol, so an unknown one and everything after it is kept as Unknown.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Ip4(Ipv4Addr),
+    Ip6(Ipv6Addr),
+    Ip6Zone(String),
+    Dns(String),
+    Dns4(String),
+    Dns6(String),
+    DnsAddr(String),
+    Tcp(u16),
+    Udp(u16),
+    Quic,
+    QuicV1,
+    WebTransport,
+    WebRtcDirect,
+    Certhash(String),
+    Tls,
+    Sni(String),
+    Noise,
+    Ws,
+    Wss,
+    Http,
+    Https,
+    P2p(String),
+    P2pCircuit,
+    // The rest of the address from the first unknown protocol on, without the leading /, e.g. webrtc/p2p/Qm...
+    Unknown(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Multiaddr {
+    pub protocols: Vec<Protocol>,
+}
+
+impl Multiaddr {
+    // The part of the address before /p2p-circuit, which for a relayed address is the relay's own address.
+    fn direct(&self) -> &[Protocol] {
+        let end = self.protocols.iter()
+            .position(|v| *v == Protocol::P2pCircuit)
+            .unwrap_or(self.protocols.len());
+
+        &self.protocols[..end]
+    }
+
+    pub fn is_relay(&self) -> bool {
+        self.protocols.iter().any(|v| match v {
+            Protocol::P2pCircuit => true,
+            Protocol::Unknown(rest) => rest.split('/').any(|v| v == "p2p-circuit"),
+            _ => false,
+        })
+    }
+
+    pub fn zone(&self) -> Option<&str> {
+        self.direct().iter().find_map(|v| match v {
+            Protocol::Ip6Zone(zone) => Some(zone.as_str()),
+            _ => None,
+        })
+    }
+
+    // The IP or DNS component the address dials.
+    pub fn host_protocol(&self) -> Option<&Protocol> {
+        self.direct().iter().find(|v| matches!(v,
+            Protocol::Ip4(_) | Protocol::Ip6(_) | Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::DnsAddr(_)))
+    }
+
+    pub fn host(&self) -> Option<String> {
+        self.direct().iter().find_map(|v| match v {
+            Protocol::Ip4(a) => Some(a.to_string()),
+            Protocol::Ip6(a) => Some(a.to_string()),
+            Protocol::Dns(a) | Protocol::Dns4(a) | Protocol::Dns6(a) | Protocol::DnsAddr(a) => Some(a.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.direct().iter().find_map(|v| match v {
+            Protocol::Tcp(port) | Protocol::Udp(port) => Some(*port),
+            _ => None,
+        })
+    }
+
+    // The outermost transport, so /udp/4001/quic-v1/webtransport is webtransport rather than udp.
+    pub fn transport(&self) -> Option<&'static str> {
+        self.direct().iter().fold(None, |transport, v| match v {
+            Protocol::Tcp(_) => Some("tcp"),
+            Protocol::Udp(_) => Some("udp"),
+            Protocol::Quic => Some("quic"),
+            Protocol::QuicV1 => Some("quic-v1"),
+            Protocol::WebTransport => Some("webtransport"),
+            Protocol::WebRtcDirect => Some("webrtc-direct"),
+            Protocol::Ws => Some("ws"),
+            Protocol::Wss => Some("wss"),
+            _ => transport,
+        })
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Ip4(v) => write!(f, "/ip4/{}", v),
+            Protocol::Ip6(v) => write!(f, "/ip6/{}", v),
+            Protocol::Ip6Zone(v) => write!(f, "/ip6zone/{}", v),
+            Protocol::Dns(v) => write!(f, "/dns/{}", v),
+            Protocol::Dns4(v) => write!(f, "/dns4/{}", v),
+            Protocol::Dns6(v) => write!(f, "/dns6/{}", v),
+            Protocol::DnsAddr(v) => write!(f, "/dnsaddr/{}", v),
+            Protocol::Tcp(v) => write!(f, "/tcp/{}", v),
+            Protocol::Udp(v) => write!(f, "/udp/{}", v),
+            Protocol::Quic => write!(f, "/quic"),
+            Protocol::QuicV1 => write!(f, "/quic-v1"),
+            Protocol::WebTransport => write!(f, "/webtransport"),
+            Protocol::WebRtcDirect => write!(f, "/webrtc-direct"),
+            Protocol::Certhash(v) => write!(f, "/certhash/{}", v),
+            Protocol::Tls => write!(f, "/tls"),
+            Protocol::Sni(v) => write!(f, "/sni/{}", v),
+            Protocol::Noise => write!(f, "/noise"),
+            Protocol::Ws => write!(f, "/ws"),
+            Protocol::Wss => write!(f, "/wss"),
+            Protocol::Http => write!(f, "/http"),
+            Protocol::Https => write!(f, "/https"),
+            Protocol::P2p(v) => write!(f, "/p2p/{}", v),
+            Protocol::P2pCircuit => write!(f, "/p2p-circuit"),
+            Protocol::Unknown(v) => write!(f, "/{}", v),
+        }
+    }
+}
+
+impl fmt::Display for Multiaddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in &self.protocols {
+            write!(f, "{}", v)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Multiaddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Multiaddr, String> {
+        let mut parts = match s.strip_prefix('/') {
+            Some(v) => v.split('/'),
+            None => return Err("doesn't start with /".to_owned()),
+        };
+
+        let mut protocols = Vec::new();
+
+        while let Some(name) = parts.next() {
+            let mut value = || parts.next()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| format!("/{} is missing its value", name));
+
+            let protocol = match name {
+                "ip4" => Protocol::Ip4(value()?.parse().map_err(|e| format!("/ip4: {}", e))?),
+                "ip6" => Protocol::Ip6(value()?.parse().map_err(|e| format!("/ip6: {}", e))?),
+                "ip6zone" => Protocol::Ip6Zone(value()?.to_owned()),
+                "dns" => Protocol::Dns(value()?.to_owned()),
+                "dns4" => Protocol::Dns4(value()?.to_owned()),
+                "dns6" => Protocol::Dns6(value()?.to_owned()),
+                "dnsaddr" => Protocol::DnsAddr(value()?.to_owned()),
+                "tcp" => Protocol::Tcp(value()?.parse().map_err(|e| format!("/tcp: {}", e))?),
+                "udp" => Protocol::Udp(value()?.parse().map_err(|e| format!("/udp: {}", e))?),
+                "quic" => Protocol::Quic,
+                "quic-v1" => Protocol::QuicV1,
+                "webtransport" => Protocol::WebTransport,
+                "webrtc-direct" => Protocol::WebRtcDirect,
+                "certhash" => Protocol::Certhash(value()?.to_owned()),
+                "tls" => Protocol::Tls,
+                "sni" => Protocol::Sni(value()?.to_owned()),
+                "noise" => Protocol::Noise,
+                "ws" => Protocol::Ws,
+                "wss" => Protocol::Wss,
+                "http" => Protocol::Http,
+                "https" => Protocol::Https,
+                // /ipfs is the old name of /p2p
+                "p2p" | "ipfs" => Protocol::P2p(value()?.to_owned()),
+                "p2p-circuit" => Protocol::P2pCircuit,
+                "" => return Err("empty protocol".to_owned()),
+                _ => {
+                    let rest: Vec<&str> = std::iter::once(name).chain(parts.by_ref()).collect();
+                    Protocol::Unknown(rest.join("/"))
+                }
+            };
+
+            protocols.push(protocol);
+        }
+
+        Ok(Multiaddr { protocols })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Multiaddr {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    #[test]
+    fn parses_known_protocols() {
+        let v = parse("/ip4/192.0.2.1/tcp/4001/p2p/QmNode");
+        assert_eq!(v.protocols, vec![
+            Protocol::Ip4("192.0.2.1".parse().unwrap()),
+            Protocol::Tcp(4001),
+            Protocol::P2p("QmNode".to_owned()),
+        ]);
+        assert_eq!(v.host().as_deref(), Some("192.0.2.1"));
+        assert_eq!(v.port(), Some(4001));
+        assert_eq!(v.transport(), Some("tcp"));
+        assert!(!v.is_relay());
+
+        let v = parse("/dns4/example.com/udp/443/quic-v1/webtransport/certhash/uEiA");
+        assert_eq!(v.host().as_deref(), Some("example.com"));
+        assert_eq!(v.transport(), Some("webtransport"));
+
+        let v = parse("/ip6/fe80::1/ip6zone/eth0/tcp/4001");
+        assert_eq!(v.zone(), Some("eth0"));
+
+        // /ipfs is the old name of /p2p
+        assert_eq!(parse("/ipfs/QmNode").protocols, vec![Protocol::P2p("QmNode".to_owned())]);
+    }
+
+    #[test]
+    fn relayed_addresses_use_the_relay_part() {
+        let v = parse("/ip4/192.0.2.1/tcp/4001/p2p/QmRelay/p2p-circuit/p2p/QmNode");
+        assert!(v.is_relay());
+        assert_eq!(v.host().as_deref(), Some("192.0.2.1"));
+        assert_eq!(v.port(), Some(4001));
+    }
+
+    #[test]
+    fn keeps_unknown_protocols() {
+        for s in &[
+            "/ip4/192.0.2.1/udp/4001/webrtc/certhash/uEiA/p2p/QmNode",
+            "/dns4/example.com/tcp/443/wss/p2p-webrtc-star/p2p/QmNode",
+            "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234",
+            "/unix/tmp/ipfs.sock",
+        ] {
+            let v = parse(s);
+            assert!(matches!(v.protocols.last(), Some(Protocol::Unknown(_))), "{}", s);
+            assert_eq!(v.to_string(), *s);
+        }
+
+        let v = parse("/ip4/192.0.2.1/udp/4001/webrtc/p2p/QmNode");
+        assert_eq!(v.host().as_deref(), Some("192.0.2.1"));
+        assert_eq!(v.port(), Some(4001));
+        assert_eq!(v.protocols[2], Protocol::Unknown("webrtc/p2p/QmNode".to_owned()));
+
+        assert!(parse("/ip4/192.0.2.1/tcp/4001/p2p/QmRelay/webrtc/p2p-circuit/p2p/QmNode").is_relay());
+
+        // Everything after an unknown protocol is opaque, so an address that starts with one has no host to probe
+        let v = parse("/ip9/192.0.2.1/tcp/4001");
+        assert_eq!(v.protocols, vec![Protocol::Unknown("ip9/192.0.2.1/tcp/4001".to_owned())]);
+        assert_eq!(v.host(), None);
+        assert_eq!(v.transport(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for s in &[
+            "",
+            "ip4/192.0.2.1",
+            "/ip4/192.0.2.300/tcp/4001",
+            "/ip4/192.0.2.1/tcp/70000",
+            "/ip4/192.0.2.1/tcp",
+            "/ip4/192.0.2.1/tcp/",
+            "/ip6/not-an-address",
+            "//tcp/4001",
+        ] {
+            assert!(s.parse::<Multiaddr>().is_err(), "{} parsed", s);
+        }
+    }
+}